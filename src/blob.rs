@@ -1,36 +1,60 @@
-use crate::object_collection::Properties;
+use crate::object_collection::{impl_delta, Properties};
 
 pub struct Blob {
     hash_index: usize,
-    size: u32,
-    size_disk: u32,
-    path: String,
+    size: u64,
+    size_disk: u64,
+    delta_base: Option<usize>,
+    delta_depth: u32,
+    paths: Vec<u32>,
     commits: Vec<usize>,
 }
 
 impl Blob {
     // Constructor
-    pub fn new(hash_index: usize, size: u32, size_disk: u32) -> Blob {
+    pub fn new(hash_index: usize, size: u64, size_disk: u64) -> Blob {
         Blob {
             hash_index,
             size,
             size_disk,
-            path: "".to_string(),
+            delta_base: None,
+            delta_depth: 0,
+            paths: Vec::new(),
             commits: Vec::new(),
         }
     }
 
-    pub fn size_disk(&self) -> u32 {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn size_disk(&self) -> u64 {
         self.size_disk
     }
 
-    pub fn add_path(&mut self, path: &str) {
-        self.path = path.to_string();
+    /// Record a file path this blob was committed at, as an id from the containers path
+    /// table. Each path is only kept once.
+    pub fn add_path(&mut self, path_id: u32) {
+        if let Err(position) = self.paths.binary_search(&path_id) {
+            self.paths.insert(position, path_id);
+        }
+    }
+
+    /// The file paths this blob was committed at, as path table ids. The same contents at
+    /// several paths, or a file that was renamed, give a blob more than one. Ids are handed
+    /// out in the order commits happen to be read, so use `ObjectContainer::resolve_paths`
+    /// rather than relying on the order here.
+    pub fn paths(&self) -> &Vec<u32> {
+        &self.paths
     }
 
     pub fn add_commit(&mut self, commit_index: &usize) {
         self.commits.push(*commit_index);
     }
+
+    pub fn commits(&self) -> &Vec<usize> {
+        &self.commits
+    }
     // Method to display tree information
     pub fn display_info(&self) {
         println!("Hash: {}", self.hash_index);
@@ -48,3 +72,5 @@ impl Properties for Blob {
         self.hash_index = index;
     }
 }
+
+impl_delta!(Blob);
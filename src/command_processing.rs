@@ -1,33 +1,139 @@
-use std::io::{BufReader, Read};
+use crate::error::{Error, Result};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
 
-pub fn run_command(command_path: &Path, command: &str, args: &[&str]) -> Result<String, String> {
+pub fn run_command(command_path: &Path, command: &str, args: &[&str]) -> Result<String> {
     let output = Command::new(command)
         .current_dir(command_path)
         .args(args)
         .output()
-        .map_err(|e| format!("Failed to execute command: {e}"))?;
+        .map_err(|e| spawn_error(command, e))?;
 
     if output.status.success() {
         let git_output = String::from_utf8_lossy(&output.stdout);
         Ok(git_output.trim().to_string())
     } else {
-        let error_message = format!(
-            "Command failed to execute\nError:\n{}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        Err(error_message)
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(command_failed(
+            command_path,
+            command,
+            args,
+            output.status,
+            stderr,
+        ))
     }
 }
 
+// The error for a command that ran but exited with `status`.
+fn command_failed(
+    command_path: &Path,
+    command: &str,
+    args: &[&str],
+    status: ExitStatus,
+    stderr: String,
+) -> Error {
+    if command == "git" && stderr.contains("not a git repository") {
+        return Error::NotARepo(command_path.to_path_buf());
+    }
+    Error::CommandFailed {
+        command: format!("{command} {}", args.join(" ")),
+        status,
+        stderr,
+    }
+}
+
+// Collect what a command writes to stderr on a thread of its own, so a command writing a lot
+// of errors can not block while its output is still being read.
+fn read_stderr(process: &mut Child) -> JoinHandle<String> {
+    let mut stderr = process
+        .stderr
+        .take()
+        .expect("Failed to get stderr of command");
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        // Whatever was read before an error is still worth reporting.
+        let _ = stderr.read_to_end(&mut bytes);
+        String::from_utf8_lossy(&bytes).trim().to_string()
+    })
+}
+
+// Tell a missing git apart from any other failure to start a command.
+fn spawn_error(command: &str, e: io::Error) -> Error {
+    if command == "git" && e.kind() == ErrorKind::NotFound {
+        Error::GitNotFound
+    } else {
+        Error::Io(e)
+    }
+}
+
+/// Run a command, calling `on_line` with each line of its output as it is read rather than
+/// collecting the whole output. The line is passed without its newline.
+pub fn run_command_lines(
+    command_path: &Path,
+    command: &str,
+    args: &[&str],
+    on_line: &mut dyn FnMut(&str),
+) -> Result<()> {
+    let mut process = Command::new(command)
+        .current_dir(command_path)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error(command, e))?;
+    let stderr = read_stderr(&mut process);
+
+    let mut reader = BufReader::new(
+        process
+            .stdout
+            .as_mut()
+            .expect("Failed to get stdout of command"),
+    );
+
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        on_line(line.trim_end_matches(['\n', '\r']));
+        line.clear();
+    }
+
+    let status = process.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        return Err(command_failed(command_path, command, args, status, stderr));
+    }
+
+    Ok(())
+}
+
 pub fn pipe_commands(
     command_path: &Path,
     cmd1: &str,
     args1: &[&str],
     cmd2: &str,
     args2: &[&str],
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<String> {
+    let mut result = String::new();
+    pipe_commands_lines(command_path, cmd1, args1, cmd2, args2, &mut |line: &str| {
+        result.push_str(line);
+        result.push('\n');
+    })?;
+
+    Ok(result)
+}
+
+/// Pipe the output of one command into another, `cmd1 | cmd2`, calling `on_line` with each
+/// line of the second command's output as it is read. Only one line is held in memory at a
+/// time, and the line is passed without its newline.
+pub fn pipe_commands_lines(
+    command_path: &Path,
+    cmd1: &str,
+    args1: &[&str],
+    cmd2: &str,
+    args2: &[&str],
+    on_line: &mut dyn FnMut(&str),
+) -> Result<()> {
     // Create the first command
     let mut first_cmd = Command::new(cmd1);
     first_cmd.current_dir(command_path);
@@ -40,10 +146,13 @@ pub fn pipe_commands(
 
     // Set up the pipe
     first_cmd.stdout(Stdio::piped());
+    first_cmd.stderr(Stdio::piped());
     second_cmd.stdin(Stdio::piped());
+    second_cmd.stderr(Stdio::piped());
 
     // Start the first command
-    let mut first_process = first_cmd.spawn()?;
+    let mut first_process = first_cmd.spawn().map_err(|e| spawn_error(cmd1, e))?;
+    let first_stderr = read_stderr(&mut first_process);
 
     // Get the output of the first command
     let first_output = first_process
@@ -53,9 +162,13 @@ pub fn pipe_commands(
 
     // Start the second command, using the output of the first as input
     second_cmd.stdin(Stdio::from(first_output));
-    let mut second_process = second_cmd.stdout(Stdio::piped()).spawn()?;
+    let mut second_process = second_cmd
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error(cmd2, e))?;
+    let second_stderr = read_stderr(&mut second_process);
 
-    // Read and print the output of the second command
+    // Read the output of the second command a line at a time, reusing the buffer.
     let mut reader = BufReader::new(
         second_process
             .stdout
@@ -63,14 +176,38 @@ pub fn pipe_commands(
             .expect("Failed to get stdout of second command"),
     );
 
-    let mut result = String::new();
-    reader.read_to_string(&mut result)?;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        on_line(line.trim_end_matches(['\n', '\r']));
+        line.clear();
+    }
 
-    // Wait for both processes to finish
-    first_process.wait()?;
-    second_process.wait()?;
+    // Wait for both processes to finish. If either command failed the output read above may
+    // be cut short, so the result can not be trusted.
+    let first_status = first_process.wait()?;
+    let second_status = second_process.wait()?;
+    let first_stderr = first_stderr.join().unwrap_or_default();
+    let second_stderr = second_stderr.join().unwrap_or_default();
+    if !first_status.success() {
+        return Err(command_failed(
+            command_path,
+            cmd1,
+            args1,
+            first_status,
+            first_stderr,
+        ));
+    }
+    if !second_status.success() {
+        return Err(command_failed(
+            command_path,
+            cmd2,
+            args2,
+            second_status,
+            second_stderr,
+        ));
+    }
 
-    Ok(result)
+    Ok(())
 }
 
 #[cfg(test)]
@@ -78,6 +215,7 @@ mod tests {
     use super::*;
     use std::env::temp_dir;
 
+    #[cfg(windows)]
     #[test]
     fn test_run_command_success() {
         let temp_dir = temp_dir();
@@ -92,10 +230,17 @@ mod tests {
         let temp_dir = temp_dir();
         let command_path = temp_dir.as_path();
         let result = run_command(command_path, "nonexistent_command", &[]);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Failed to execute command"));
+        assert!(matches!(result, Err(Error::Io(_))));
     }
 
+    #[test]
+    fn test_run_command_not_a_repo() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let result = run_command(dir.path(), "git", &["rev-parse", "--git-dir"]);
+        assert!(matches!(result, Err(Error::NotARepo(_))));
+    }
+
+    #[cfg(windows)]
     #[test]
     fn test_pipe_commands_success() {
         let temp_dir = temp_dir();
@@ -111,6 +256,7 @@ mod tests {
         assert_eq!(result.unwrap().trim(), "\"Hello, world!\"");
     }
 
+    #[cfg(windows)]
     #[test]
     fn test_pipe_commands_failure() {
         let temp_dir = temp_dir();
@@ -122,7 +268,109 @@ mod tests {
             "cmd",
             &["/C", "findstr", "Nonexistent"],
         );
+        assert!(matches!(result, Err(Error::CommandFailed { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_command_success_unix() {
+        let temp_dir = temp_dir();
+        let command_path = temp_dir.as_path();
+        let result = run_command(command_path, "echo", &["Hello, world!"]);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Hello, world!");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pipe_commands_success_unix() {
+        let temp_dir = temp_dir();
+        let command_path = temp_dir.as_path();
+        let result = pipe_commands(command_path, "echo", &["Hello, world!"], "grep", &["Hello"]);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap().trim(), "");
+        assert_eq!(result.unwrap().trim(), "Hello, world!");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pipe_commands_failure_unix() {
+        let temp_dir = temp_dir();
+        let command_path = temp_dir.as_path();
+        let result = pipe_commands(
+            command_path,
+            "echo",
+            &["Hello, world!"],
+            "grep",
+            &["Nonexistent"],
+        );
+        // grep exits with 1 when nothing matches.
+        assert!(matches!(result, Err(Error::CommandFailed { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pipe_commands_lines_second_fails_unix() {
+        let temp_dir = temp_dir();
+        let mut lines = Vec::new();
+        let result = pipe_commands_lines(
+            temp_dir.as_path(),
+            "printf",
+            &["one\\ntwo\\nthree\\n"],
+            "sh",
+            &[
+                "-c",
+                "read line; echo \"$line\"; cat > /dev/null; echo broken >&2; exit 3",
+            ],
+            &mut |line: &str| lines.push(line.to_string()),
+        );
+        assert_eq!(lines, vec!["one"]);
+        match result {
+            Err(Error::CommandFailed {
+                command,
+                status,
+                stderr,
+            }) => {
+                assert!(command.starts_with("sh "));
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "broken");
+            }
+            other => panic!("expected CommandFailed, got {other:?}"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_command_lines_failure_unix() {
+        let temp_dir = temp_dir();
+        let result = run_command_lines(
+            temp_dir.as_path(),
+            "sh",
+            &["-c", "echo out; echo oops >&2; exit 2"],
+            &mut |_: &str| {},
+        );
+        match result {
+            Err(Error::CommandFailed { status, stderr, .. }) => {
+                assert_eq!(status.code(), Some(2));
+                assert_eq!(stderr, "oops");
+            }
+            other => panic!("expected CommandFailed, got {other:?}"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pipe_commands_lines_unix() {
+        let temp_dir = temp_dir();
+        let mut lines = Vec::new();
+        pipe_commands_lines(
+            temp_dir.as_path(),
+            "printf",
+            &["one\\ntwo\\nthree\\n"],
+            "grep",
+            &["-v", "two"],
+            &mut |line: &str| lines.push(line.to_string()),
+        )
+        .unwrap();
+        assert_eq!(lines, vec!["one", "three"]);
     }
 }
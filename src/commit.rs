@@ -1,34 +1,88 @@
-use crate::object_collection::Properties;
+use crate::object_collection::{impl_delta, Properties};
+
+/// Who made a commit and when, along with its subject line. The strings are ids into the
+/// containers string interner, see `ObjectContainer::resolve_string`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommitMetadata {
+    pub author_name: u32,
+    pub author_email: u32,
+    /// Seconds since the unix epoch.
+    pub author_time: i64,
+    /// Seconds since the unix epoch.
+    pub commit_time: i64,
+    pub subject: u32,
+}
 
 #[derive(Debug, Default)]
 pub struct Commit {
     hash_index: usize,
-    size: u32,
-    size_disk: u32,
+    size: u64,
+    size_disk: u64,
+    delta_base: Option<usize>,
+    delta_depth: u32,
+    parents: Vec<usize>,
+    children: Vec<usize>,
+    metadata: Option<CommitMetadata>,
     blob_deps: Vec<usize>,
     tree_deps: Vec<usize>,
     tag_deps: Vec<usize>,
     lightweight_tags: Vec<String>,
+    deps_processed: bool,
 }
 
 impl Commit {
     // Constructor
-    pub fn new(hash_index: usize, size: u32, size_disk: u32) -> Self {
+    pub fn new(hash_index: usize, size: u64, size_disk: u64) -> Self {
         Commit {
             hash_index,
             size,
             size_disk,
+            delta_base: None,
+            delta_depth: 0,
+            parents: Vec::new(),
+            children: Vec::new(),
+            metadata: None,
             blob_deps: Vec::new(),
             tree_deps: Vec::new(),
             tag_deps: Vec::new(),
             lightweight_tags: Vec::new(),
+            deps_processed: false,
         }
     }
 
-    pub fn size_disk(&self) -> u32 {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn size_disk(&self) -> u64 {
         self.size_disk
     }
 
+    pub fn add_parent(&mut self, parent_index: &usize) {
+        self.parents.push(*parent_index);
+    }
+
+    /// The parents of this commit in order, the first parent first.
+    pub fn parents(&self) -> &Vec<usize> {
+        &self.parents
+    }
+
+    pub fn add_child(&mut self, child_index: &usize) {
+        self.children.push(*child_index);
+    }
+
+    pub fn children(&self) -> &Vec<usize> {
+        &self.children
+    }
+
+    pub fn set_metadata(&mut self, metadata: CommitMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    pub fn metadata(&self) -> Option<&CommitMetadata> {
+        self.metadata.as_ref()
+    }
+
     pub fn add_blob_dep(&mut self, blob_index: &usize) {
         self.blob_deps.push(*blob_index);
     }
@@ -45,8 +99,11 @@ impl Commit {
         &self.tree_deps
     }
 
+    /// Each tag is only kept once, so tags can be processed again after an update.
     pub fn add_tag_dep(&mut self, tag_index: &usize) {
-        self.tag_deps.push(*tag_index);
+        if !self.tag_deps.contains(tag_index) {
+            self.tag_deps.push(*tag_index);
+        }
     }
 
     pub fn tag_deps(&self) -> &Vec<usize> {
@@ -54,13 +111,29 @@ impl Commit {
     }
 
     pub fn add_lightweight_tag(&mut self, lightweight_tag: &str) {
-        self.lightweight_tags.push(lightweight_tag.to_string());
+        if !self
+            .lightweight_tags
+            .iter()
+            .any(|tag| tag == lightweight_tag)
+        {
+            self.lightweight_tags.push(lightweight_tag.to_string());
+        }
     }
 
     pub fn lightweight_tags(&self) -> &Vec<String> {
         &self.lightweight_tags
     }
 
+    /// Mark the trees and blobs this commit introduced as linked, so they are not linked a
+    /// second time when the container is updated with new commits.
+    pub fn set_deps_processed(&mut self) {
+        self.deps_processed = true;
+    }
+
+    pub fn deps_processed(&self) -> bool {
+        self.deps_processed
+    }
+
     // Method to display tree information
     pub fn display_info(&self) {
         print!("Hash: {}", self.hash_index);
@@ -78,3 +151,5 @@ impl Properties for Commit {
         self.hash_index = index;
     }
 }
+
+impl_delta!(Commit);
@@ -0,0 +1,61 @@
+use std::{io, path::PathBuf, process::ExitStatus};
+use thiserror::Error;
+
+/// Everything that can go wrong reading a repo and building up the container.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// git could not be started, it is most likely not installed or not on the PATH.
+    #[error("git was not found, make sure it is installed and on the PATH")]
+    GitNotFound,
+
+    #[error("not a git repository: {}", .0.display())]
+    NotARepo(PathBuf),
+
+    /// A command ran but exited with an error, along with what it wrote to stderr.
+    #[error("`{command}` failed with {status}: {stderr}")]
+    CommandFailed {
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+
+    /// Output from git, or a file in the repo, was not in the expected form.
+    #[error("malformed {what}: {line}")]
+    MalformedOutput { what: String, line: String },
+
+    /// A saved deps file or snapshot could not be read back.
+    #[error("cache {} is corrupt: {reason}", path.display())]
+    CacheCorrupt { path: PathBuf, reason: String },
+
+    /// A snapshot was taken of a different repo, object format or scope than the one asked
+    /// for.
+    #[error("snapshot {} was {reason}", path.display())]
+    SnapshotMismatch { path: PathBuf, reason: String },
+
+    /// The repo uses something the chosen backend can not read.
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Git2(#[from] git2::Error),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+impl Error {
+    pub fn malformed(what: &str, line: &str) -> Self {
+        Error::MalformedOutput {
+            what: what.to_string(),
+            line: line.to_string(),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,269 @@
+use crate::error::Result;
+use crate::object_collection::{BasicObjectContainer, Delta, ObjectContainer, Properties};
+use crate::report_commits::calc_commit_size;
+use csv::Writer;
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::Instant,
+};
+
+/// Write every object in the container to a set of CSV files in `dir`, creating it if needed.
+/// Hashes are written in hex and paths in full, so the files can be loaded as they are.
+///
+/// One row per object in `commits.csv`, `trees.csv`, `blobs.csv` and `tags.csv`. Anything an
+/// object has more than one of is written one value per row to an edge table, as a path or
+/// tag name can hold any separator a single column would use:
+/// - `commit_parents.csv`: commit, parent and position, the first parent is 0.
+/// - `commit_trees.csv` and `commit_blobs.csv`: the trees and blobs each commit introduced,
+///   that is changed from its first parent, so a moved or reverted blob has more than one.
+/// - `tree_paths.csv` and `blob_paths.csv`: every path a tree or blob was seen at.
+/// - `lightweight_tags.csv`: the commit each lightweight tag points at.
+/// - `refs.csv`: the commit each ref points at, when the refs were read.
+pub fn export_csv(container: &ObjectContainer, dir: &Path) -> Result<()> {
+    eprintln!("Exporting CSV files to {}...", dir.display());
+    let start = Instant::now();
+    fs::create_dir_all(dir)?;
+
+    let commit_hash = |index: &usize| hash(container.commits(), *index);
+    let resolve = |id: u32| container.resolve_string(id).unwrap_or_default();
+
+    let mut commits = writer(dir, "commits.csv")?;
+    commits.write_record([
+        "hash",
+        "size",
+        "size_disk",
+        "delta_depth",
+        "delta_base",
+        "introduced_size_disk",
+        "parent_count",
+        "child_count",
+        "author_name",
+        "author_email",
+        "author_time",
+        "commit_time",
+        "subject",
+    ])?;
+    let mut parents = writer(dir, "commit_parents.csv")?;
+    parents.write_record(["commit", "parent", "position"])?;
+    let mut commit_trees = writer(dir, "commit_trees.csv")?;
+    commit_trees.write_record(["commit", "tree"])?;
+    let mut commit_blobs = writer(dir, "commit_blobs.csv")?;
+    commit_blobs.write_record(["commit", "blob"])?;
+    let mut lightweight_tags = writer(dir, "lightweight_tags.csv")?;
+    lightweight_tags.write_record(["name", "commit"])?;
+
+    for index in 0..container.commits().count() {
+        let commit = container.commits().get_by_index(&index).read().unwrap();
+        let hash = commit_hash(&index);
+        let metadata = commit.metadata();
+        commits.write_record([
+            hash.clone(),
+            commit.size().to_string(),
+            commit.size_disk().to_string(),
+            commit.delta_depth().to_string(),
+            optional_hash(container.commits(), commit.delta_base()),
+            calc_commit_size(&commit, container).to_string(),
+            commit.parents().len().to_string(),
+            commit.children().len().to_string(),
+            metadata.map_or(String::new(), |m| resolve(m.author_name).to_string()),
+            metadata.map_or(String::new(), |m| resolve(m.author_email).to_string()),
+            metadata.map_or(String::new(), |m| m.author_time.to_string()),
+            metadata.map_or(String::new(), |m| m.commit_time.to_string()),
+            metadata.map_or(String::new(), |m| resolve(m.subject).to_string()),
+        ])?;
+
+        for (position, parent) in commit.parents().iter().enumerate() {
+            parents.write_record([hash.clone(), commit_hash(parent), position.to_string()])?;
+        }
+        for tree in commit.tree_deps() {
+            commit_trees.write_record([hash.clone(), self::hash(container.trees(), *tree)])?;
+        }
+        for blob in commit.blob_deps() {
+            commit_blobs.write_record([hash.clone(), self::hash(container.blobs(), *blob)])?;
+        }
+        for name in commit.lightweight_tags() {
+            lightweight_tags.write_record([name.clone(), hash.clone()])?;
+        }
+    }
+    for mut file in [
+        commits,
+        parents,
+        commit_trees,
+        commit_blobs,
+        lightweight_tags,
+    ] {
+        file.flush()?;
+    }
+
+    write_path_objects(container, dir, "tree", container.trees(), |tree| {
+        (tree.size(), tree.size_disk(), tree.paths(), tree.commits())
+    })?;
+    write_path_objects(container, dir, "blob", container.blobs(), |blob| {
+        (blob.size(), blob.size_disk(), blob.paths(), blob.commits())
+    })?;
+
+    let mut tags = writer(dir, "tags.csv")?;
+    tags.write_record([
+        "hash",
+        "name",
+        "size",
+        "size_disk",
+        "delta_depth",
+        "delta_base",
+        "commit",
+    ])?;
+    for index in 0..container.tags().count() {
+        let tag = container.tags().get_by_index(&index).read().unwrap();
+        tags.write_record([
+            hash(container.tags(), index),
+            tag.name().to_string(),
+            tag.size().to_string(),
+            tag.size_disk().to_string(),
+            tag.delta_depth().to_string(),
+            optional_hash(container.tags(), tag.delta_base()),
+            optional_hash(container.commits(), tag.commit_dep()),
+        ])?;
+    }
+    tags.flush()?;
+
+    let mut refs = writer(dir, "refs.csv")?;
+    refs.write_record(["name", "commit"])?;
+    for (name, commit) in container.refs() {
+        refs.write_record([name, commit_hash(&commit)])?;
+    }
+    refs.flush()?;
+
+    eprintln!("Done exporting CSV files in: {:?}", start.elapsed());
+    Ok(())
+}
+
+// Write `<kind>s.csv` and `<kind>_paths.csv` for trees or blobs. `fields` returns the size,
+// size on disk, path ids and introducing commits of an object. Only the number of paths goes
+// in `<kind>s.csv`, the paths themselves are in `<kind>_paths.csv`.
+fn write_path_objects<T: Properties + Delta>(
+    container: &ObjectContainer,
+    dir: &Path,
+    kind: &str,
+    objects: &BasicObjectContainer<T>,
+    fields: impl Fn(&T) -> (u64, u64, &[u32], &[usize]),
+) -> Result<()> {
+    let mut rows = writer(dir, &format!("{kind}s.csv"))?;
+    rows.write_record([
+        "hash",
+        "size",
+        "size_disk",
+        "delta_depth",
+        "delta_base",
+        "path_count",
+        "commit_count",
+    ])?;
+    let mut paths = writer(dir, &format!("{kind}_paths.csv"))?;
+    paths.write_record([kind, "path"])?;
+
+    for index in 0..objects.count() {
+        let object = objects.get_by_index(&index).read().unwrap();
+        let (size, size_disk, path_ids, commits) = fields(&object);
+        let hash = hash(objects, index);
+        let resolved = container.resolve_paths(path_ids);
+
+        rows.write_record([
+            hash.clone(),
+            size.to_string(),
+            size_disk.to_string(),
+            object.delta_depth().to_string(),
+            optional_hash(objects, object.delta_base()),
+            resolved.len().to_string(),
+            commits.len().to_string(),
+        ])?;
+        for path in resolved {
+            paths.write_record([hash.clone(), path])?;
+        }
+    }
+
+    rows.flush()?;
+    paths.flush()?;
+    Ok(())
+}
+
+fn writer(dir: &Path, name: &str) -> Result<Writer<File>> {
+    Ok(Writer::from_path(dir.join(name))?)
+}
+
+// Every index in the container has a hash, so the lookup can not fail.
+fn hash<T: Properties>(objects: &BasicObjectContainer<T>, index: usize) -> String {
+    objects.lookup_hash_for_index(&index).unwrap().to_string()
+}
+
+fn optional_hash<T: Properties>(objects: &BasicObjectContainer<T>, index: Option<usize>) -> String {
+    index.map_or(String::new(), |index| hash(objects, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_source::{InMemorySource, ObjectKind};
+    use tempfile::TempDir;
+
+    const FIRST_COMMIT: &str = "1111111111111111111111111111111111111111";
+    const SECOND_COMMIT: &str = "2222222222222222222222222222222222222222";
+    const TREE: &str = "3333333333333333333333333333333333333333";
+    const BLOB: &str = "4444444444444444444444444444444444444444";
+    const TAG: &str = "5555555555555555555555555555555555555555";
+
+    #[tokio::test]
+    async fn test_export_csv() {
+        let mut source = InMemorySource::new();
+        source.add_object(ObjectKind::Commit, FIRST_COMMIT, 200, 150);
+        source.add_object(ObjectKind::Commit, SECOND_COMMIT, 210, 160);
+        source.add_object(ObjectKind::Tree, TREE, 100, 80);
+        source.add_object(ObjectKind::Blob, BLOB, 1000, 500);
+        source.add_object(ObjectKind::Tag, TAG, 140, 130);
+        source.add_commit_dep(FIRST_COMMIT, TREE, "");
+        source.add_commit_dep(FIRST_COMMIT, BLOB, "src/main.rs");
+        source.add_commit_dep(SECOND_COMMIT, BLOB, "src/lib.rs");
+        source.add_commit_parents(FIRST_COMMIT, &[]);
+        source.add_commit_parents(SECOND_COMMIT, &[FIRST_COMMIT]);
+        source.add_tag_ref("v1.0", TAG, Some(SECOND_COMMIT));
+        source.add_tag_ref("semi;colon", FIRST_COMMIT, None);
+
+        let container = source.into_container().await;
+
+        let dir = TempDir::new().unwrap();
+        export_csv(&container, dir.path()).unwrap();
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+
+        let blobs = read("blobs.csv");
+        let mut lines = blobs.lines();
+        assert_eq!(
+            lines.next(),
+            Some("hash,size,size_disk,delta_depth,delta_base,path_count,commit_count")
+        );
+        assert_eq!(
+            lines.next(),
+            Some(format!("{BLOB},1000,500,0,,2,2").as_str())
+        );
+
+        assert_eq!(
+            read("commit_parents.csv"),
+            format!("commit,parent,position\n{SECOND_COMMIT},{FIRST_COMMIT},0\n")
+        );
+        assert_eq!(
+            read("blob_paths.csv"),
+            format!("blob,path\n{BLOB},src/lib.rs\n{BLOB},src/main.rs\n")
+        );
+        assert!(
+            read("tags.csv").contains(&format!("{TAG},refs/tags/v1.0,140,130,0,,{SECOND_COMMIT}"))
+        );
+        assert_eq!(
+            read("refs.csv"),
+            format!(
+                "name,commit\nrefs/tags/semi;colon,{FIRST_COMMIT}\nrefs/tags/v1.0,{SECOND_COMMIT}\n"
+            )
+        );
+        assert_eq!(
+            read("lightweight_tags.csv"),
+            format!("name,commit\nrefs/tags/semi;colon,{FIRST_COMMIT}\n")
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use crate::error::Result;
+use crate::object_collection::{BasicObjectContainer, Delta, ObjectContainer, Properties};
+use crate::report_commits::calc_commit_size;
+use rusqlite::{params, Connection, Transaction};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+// Every object table uses the index of the object in the container as its id, so the edges
+// can be written straight from the indexes the container already holds.
+const SCHEMA: &str = "
+CREATE TABLE paths (
+    id INTEGER PRIMARY KEY,
+    parent_id INTEGER REFERENCES paths(id),
+    name TEXT NOT NULL,
+    path TEXT NOT NULL
+);
+CREATE TABLE commits (
+    id INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL UNIQUE,
+    size INTEGER NOT NULL,
+    size_disk INTEGER NOT NULL,
+    delta_depth INTEGER NOT NULL,
+    delta_base_id INTEGER REFERENCES commits(id),
+    introduced_size_disk INTEGER NOT NULL,
+    author_name TEXT,
+    author_email TEXT,
+    author_time INTEGER,
+    commit_time INTEGER,
+    subject TEXT
+);
+CREATE TABLE trees (
+    id INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL UNIQUE,
+    size INTEGER NOT NULL,
+    size_disk INTEGER NOT NULL,
+    delta_depth INTEGER NOT NULL,
+    delta_base_id INTEGER REFERENCES trees(id)
+);
+CREATE TABLE blobs (
+    id INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL UNIQUE,
+    size INTEGER NOT NULL,
+    size_disk INTEGER NOT NULL,
+    delta_depth INTEGER NOT NULL,
+    delta_base_id INTEGER REFERENCES blobs(id)
+);
+CREATE TABLE tags (
+    id INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    size_disk INTEGER NOT NULL,
+    delta_depth INTEGER NOT NULL,
+    delta_base_id INTEGER REFERENCES tags(id),
+    commit_id INTEGER REFERENCES commits(id)
+);
+CREATE TABLE lightweight_tags (
+    name TEXT NOT NULL,
+    commit_id INTEGER NOT NULL REFERENCES commits(id)
+);
+CREATE TABLE refs (
+    name TEXT PRIMARY KEY,
+    commit_id INTEGER NOT NULL REFERENCES commits(id)
+);
+CREATE TABLE commit_parents (
+    commit_id INTEGER NOT NULL REFERENCES commits(id),
+    parent_id INTEGER NOT NULL REFERENCES commits(id),
+    position INTEGER NOT NULL
+);
+CREATE TABLE commit_trees (
+    commit_id INTEGER NOT NULL REFERENCES commits(id),
+    tree_id INTEGER NOT NULL REFERENCES trees(id)
+);
+CREATE TABLE commit_blobs (
+    commit_id INTEGER NOT NULL REFERENCES commits(id),
+    blob_id INTEGER NOT NULL REFERENCES blobs(id)
+);
+CREATE TABLE tree_paths (
+    tree_id INTEGER NOT NULL REFERENCES trees(id),
+    path_id INTEGER NOT NULL REFERENCES paths(id)
+);
+CREATE TABLE blob_paths (
+    blob_id INTEGER NOT NULL REFERENCES blobs(id),
+    path_id INTEGER NOT NULL REFERENCES paths(id)
+);
+";
+
+// Created after the rows are inserted, which is much faster than keeping them up to date.
+const INDEXES: &str = "
+CREATE INDEX paths_path ON paths(path);
+CREATE INDEX paths_parent ON paths(parent_id);
+CREATE INDEX commits_author_time ON commits(author_time);
+CREATE INDEX commits_commit_time ON commits(commit_time);
+CREATE INDEX trees_size_disk ON trees(size_disk);
+CREATE INDEX blobs_size_disk ON blobs(size_disk);
+CREATE INDEX tags_commit ON tags(commit_id);
+CREATE INDEX lightweight_tags_commit ON lightweight_tags(commit_id);
+CREATE INDEX refs_commit ON refs(commit_id);
+CREATE INDEX commit_parents_commit ON commit_parents(commit_id);
+CREATE INDEX commit_parents_parent ON commit_parents(parent_id);
+CREATE INDEX commit_trees_commit ON commit_trees(commit_id);
+CREATE INDEX commit_trees_tree ON commit_trees(tree_id);
+CREATE INDEX commit_blobs_commit ON commit_blobs(commit_id);
+CREATE INDEX commit_blobs_blob ON commit_blobs(blob_id);
+CREATE INDEX tree_paths_tree ON tree_paths(tree_id);
+CREATE INDEX tree_paths_path ON tree_paths(path_id);
+CREATE INDEX blob_paths_blob ON blob_paths(blob_id);
+CREATE INDEX blob_paths_path ON blob_paths(path_id);
+";
+
+/// Write the whole container to a new SQLite database at `path`, replacing any file already
+/// there. Objects, paths, refs and tags each get a table, keyed by an integer id, and every
+/// link between them is an edge table. See the README for the schema and example queries.
+///
+/// The database is built next to `path` and moved into place once complete, so a failed
+/// export never leaves a partial database behind.
+pub fn export_sqlite(container: &ObjectContainer, path: &Path) -> Result<()> {
+    eprintln!("Exporting SQLite database to {}...", path.display());
+    let start = Instant::now();
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    if temp_path.exists() {
+        fs::remove_file(&temp_path)?;
+    }
+
+    if let Err(e) = write_database(container, &temp_path) {
+        // The error is what matters, a temporary file that can not be removed is left be.
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    fs::rename(&temp_path, path)?;
+    eprintln!("Done exporting SQLite database in: {:?}", start.elapsed());
+    Ok(())
+}
+
+fn write_database(container: &ObjectContainer, path: &Path) -> Result<()> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    // SQLite only checks references when asked to. Objects reference each other in every
+    // direction, so within the transaction they are only checked once everything is in.
+    connection.execute_batch("PRAGMA foreign_keys = ON")?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+    insert_paths(&transaction, container)?;
+    insert_commits(&transaction, container)?;
+    insert_path_objects(&transaction, "tree", container.trees(), |tree| {
+        (tree.size(), tree.size_disk(), tree.paths())
+    })?;
+    insert_path_objects(&transaction, "blob", container.blobs(), |blob| {
+        (blob.size(), blob.size_disk(), blob.paths())
+    })?;
+    insert_tags(&transaction, container)?;
+    transaction.commit()?;
+    connection.execute_batch(INDEXES)?;
+    connection.close().map_err(|(_, e)| e)?;
+    Ok(())
+}
+
+fn insert_paths(transaction: &Transaction, container: &ObjectContainer) -> Result<()> {
+    let paths = container.paths().read().unwrap();
+    let mut insert = transaction
+        .prepare("INSERT INTO paths (id, parent_id, name, path) VALUES (?1, ?2, ?3, ?4)")?;
+    for id in 0..paths.count() as u32 {
+        insert.execute(params![
+            id,
+            paths.parent(id),
+            paths.name(id).unwrap_or_default(),
+            paths.resolve(id).unwrap_or_default(),
+        ])?;
+    }
+    Ok(())
+}
+
+fn insert_commits(transaction: &Transaction, container: &ObjectContainer) -> Result<()> {
+    let mut insert = transaction.prepare(
+        "INSERT INTO commits (id, hash, size, size_disk, delta_depth, delta_base_id,
+            introduced_size_disk, author_name, author_email, author_time, commit_time, subject)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )?;
+    let mut insert_parent = transaction.prepare(
+        "INSERT INTO commit_parents (commit_id, parent_id, position) VALUES (?1, ?2, ?3)",
+    )?;
+    let mut insert_tree =
+        transaction.prepare("INSERT INTO commit_trees (commit_id, tree_id) VALUES (?1, ?2)")?;
+    let mut insert_blob =
+        transaction.prepare("INSERT INTO commit_blobs (commit_id, blob_id) VALUES (?1, ?2)")?;
+    let mut insert_lightweight_tag =
+        transaction.prepare("INSERT INTO lightweight_tags (name, commit_id) VALUES (?1, ?2)")?;
+    let resolve = |id: u32| container.resolve_string(id).map(|s| s.to_string());
+
+    for index in 0..container.commits().count() {
+        let commit = container.commits().get_by_index(&index).read().unwrap();
+        let metadata = commit.metadata();
+        insert.execute(params![
+            index,
+            hash(container.commits(), index),
+            commit.size(),
+            commit.size_disk(),
+            commit.delta_depth(),
+            commit.delta_base(),
+            calc_commit_size(&commit, container),
+            metadata.and_then(|m| resolve(m.author_name)),
+            metadata.and_then(|m| resolve(m.author_email)),
+            metadata.map(|m| m.author_time),
+            metadata.map(|m| m.commit_time),
+            metadata.and_then(|m| resolve(m.subject)),
+        ])?;
+
+        for (position, parent) in commit.parents().iter().enumerate() {
+            insert_parent.execute(params![index, parent, position])?;
+        }
+        for tree in commit.tree_deps() {
+            insert_tree.execute(params![index, tree])?;
+        }
+        for blob in commit.blob_deps() {
+            insert_blob.execute(params![index, blob])?;
+        }
+        for name in commit.lightweight_tags() {
+            insert_lightweight_tag.execute(params![name, index])?;
+        }
+    }
+
+    let mut insert_ref =
+        transaction.prepare("INSERT OR REPLACE INTO refs (name, commit_id) VALUES (?1, ?2)")?;
+    for (name, commit) in container.refs() {
+        insert_ref.execute(params![name, commit])?;
+    }
+    Ok(())
+}
+
+// Fill the `<kind>s` and `<kind>_paths` tables for trees or blobs. `fields` returns the size,
+// size on disk and path ids of an object.
+fn insert_path_objects<T: Properties + Delta>(
+    transaction: &Transaction,
+    kind: &str,
+    objects: &BasicObjectContainer<T>,
+    fields: impl Fn(&T) -> (u64, u64, &[u32]),
+) -> Result<()> {
+    let mut insert = transaction.prepare(&format!(
+        "INSERT INTO {kind}s (id, hash, size, size_disk, delta_depth, delta_base_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+    ))?;
+    let mut insert_path = transaction.prepare(&format!(
+        "INSERT INTO {kind}_paths ({kind}_id, path_id) VALUES (?1, ?2)"
+    ))?;
+
+    for index in 0..objects.count() {
+        let object = objects.get_by_index(&index).read().unwrap();
+        let (size, size_disk, paths) = fields(&object);
+        insert.execute(params![
+            index,
+            hash(objects, index),
+            size,
+            size_disk,
+            object.delta_depth(),
+            object.delta_base(),
+        ])?;
+        for path in paths {
+            insert_path.execute(params![index, path])?;
+        }
+    }
+    Ok(())
+}
+
+fn insert_tags(transaction: &Transaction, container: &ObjectContainer) -> Result<()> {
+    let mut insert = transaction.prepare(
+        "INSERT INTO tags (id, hash, name, size, size_disk, delta_depth, delta_base_id, commit_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for index in 0..container.tags().count() {
+        let tag = container.tags().get_by_index(&index).read().unwrap();
+        insert.execute(params![
+            index,
+            hash(container.tags(), index),
+            tag.name(),
+            tag.size(),
+            tag.size_disk(),
+            tag.delta_depth(),
+            tag.delta_base(),
+            tag.commit_dep(),
+        ])?;
+    }
+    Ok(())
+}
+
+// Every index in the container has a hash, so the lookup can not fail.
+fn hash<T: Properties>(objects: &BasicObjectContainer<T>, index: usize) -> String {
+    objects.lookup_hash_for_index(&index).unwrap().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_source::{InMemorySource, ObjectKind};
+    use tempfile::TempDir;
+
+    const OLD_COMMIT: &str = "1111111111111111111111111111111111111111";
+    const NEW_COMMIT: &str = "2222222222222222222222222222222222222222";
+    const TREE: &str = "3333333333333333333333333333333333333333";
+    const OLD_BLOB: &str = "4444444444444444444444444444444444444444";
+    const NEW_BLOB: &str = "5555555555555555555555555555555555555555";
+    const OTHER_BLOB: &str = "6666666666666666666666666666666666666666";
+
+    #[tokio::test]
+    async fn test_export_sqlite() {
+        let mut source = InMemorySource::new();
+        source.add_object(ObjectKind::Commit, OLD_COMMIT, 200, 150);
+        source.add_object(ObjectKind::Commit, NEW_COMMIT, 210, 160);
+        source.add_object(ObjectKind::Tree, TREE, 100, 80);
+        source.add_object(ObjectKind::Blob, OLD_BLOB, 9000, 9000);
+        source.add_object(ObjectKind::Blob, NEW_BLOB, 5000, 4000);
+        source.add_object(ObjectKind::Blob, OTHER_BLOB, 7000, 7000);
+        source.add_commit_dep(OLD_COMMIT, TREE, "");
+        source.add_commit_dep(OLD_COMMIT, OLD_BLOB, "vendor/old.bin");
+        source.add_commit_dep(NEW_COMMIT, NEW_BLOB, "vendor/new.bin");
+        source.add_commit_dep(NEW_COMMIT, OTHER_BLOB, "src/other.bin");
+        source.add_commit_parents(OLD_COMMIT, &[]);
+        source.add_commit_parents(NEW_COMMIT, &[OLD_COMMIT]);
+        source.add_commit_metadata(OLD_COMMIT, ("A", "a@example.com"), 1_600_000_000, 0, "Old");
+        source.add_commit_metadata(NEW_COMMIT, ("B", "b@example.com"), 1_700_000_000, 0, "New");
+
+        let container = source.into_container().await;
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo.db");
+        // An existing file is replaced rather than added to.
+        fs::write(&path, "not a database").unwrap();
+        export_sqlite(&container, &path).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        // The largest blobs under vendor/ added after 2023.
+        let mut query = connection
+            .prepare(
+                "SELECT DISTINCT blobs.hash, blobs.size_disk FROM blobs
+                 JOIN blob_paths ON blob_paths.blob_id = blobs.id
+                 JOIN paths ON paths.id = blob_paths.path_id
+                 JOIN commit_blobs ON commit_blobs.blob_id = blobs.id
+                 JOIN commits ON commits.id = commit_blobs.commit_id
+                 WHERE paths.path LIKE 'vendor/%' AND commits.author_time >= 1672531200
+                 ORDER BY blobs.size_disk DESC",
+            )
+            .unwrap();
+        let rows: Vec<(String, i64)> = query
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows, vec![(NEW_BLOB.to_string(), 4000)]);
+
+        let parent: String = connection
+            .query_row(
+                "SELECT parents.hash FROM commit_parents
+                 JOIN commits ON commits.id = commit_parents.commit_id
+                 JOIN commits AS parents ON parents.id = commit_parents.parent_id
+                 WHERE commits.hash = ?1",
+                [NEW_COMMIT],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(parent, OLD_COMMIT);
+        assert!(!dir.path().join("repo.db.tmp").exists());
+
+        // A parent that is not in the container breaks a reference. The export fails, the
+        // database from before is kept and the temporary file is removed.
+        drop(query);
+        drop(connection);
+        let saved = fs::read(&path).unwrap();
+        let commit = container.commits().get_by_hex(NEW_COMMIT).unwrap();
+        commit.write().unwrap().add_parent(&99);
+        assert!(export_sqlite(&container, &path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), saved);
+        assert!(!dir.path().join("repo.db.tmp").exists());
+    }
+}
@@ -1,14 +1,20 @@
-use crate::command_processing::{pipe_commands, run_command};
-use std::path::Path;
+use crate::command_processing::{pipe_commands_lines, run_command, run_command_lines};
+use crate::error::{Error, Result};
+use crate::git_processing::parse_object_line;
+use crate::object_source::{push_dep, ObjectRecord, ObjectSource, Scope};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
 
-pub fn get_commit_tree_hash(repo_path: &Path, commit_hash: &str) -> Result<String, String> {
+pub fn get_commit_tree_hash(repo_path: &Path, commit_hash: &str) -> Result<String> {
     let command = "git";
     let args = ["log", "--pretty=format:%T", "-n", "1", commit_hash];
 
     run_command(repo_path, command, &args)
 }
 
-pub fn get_commit_deps(repo_path: &Path, commit_hash: &str) -> Result<String, String> {
+pub fn get_commit_deps(repo_path: &Path, commit_hash: &str) -> Result<String> {
     let command = "git";
     let commit_part = format!("{commit_hash}~1..{commit_hash}");
     let args = ["rev-list", "--objects", &commit_part];
@@ -16,35 +22,570 @@ pub fn get_commit_deps(repo_path: &Path, commit_hash: &str) -> Result<String, St
     run_command(repo_path, command, &args)
 }
 
-pub fn get_object_type(repo_path: &Path, hash: &str) -> Result<String, String> {
+pub fn get_object_type(repo_path: &Path, hash: &str) -> Result<String> {
     let command = "git";
     let args = ["cat-file", "-t", hash];
 
     run_command(repo_path, command, &args)
 }
 
-pub fn get_commit_deps_old(repo_path: &Path, commit_hash: &str) -> Result<String, String> {
+pub fn get_commit_deps_old(repo_path: &Path, commit_hash: &str) -> Result<String> {
     let command = "git";
     let args = ["diff-tree", "--no-commit-id", "-r", commit_hash];
 
     run_command(repo_path, command, &args)
 }
 
-pub fn list_objects(repo_path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+/// Stream every object in `scope` with its type, sizes and delta base, in the
+/// form `'<type> <hash> <size> <disk size> <delta base>'`, one line at a time.
+pub fn list_objects(repo_path: &Path, scope: &Scope, on_line: &mut dyn FnMut(&str)) -> Result<()> {
     let command = "git";
-    let rev_list_args = ["rev-list", "--objects", "--all", "--no-object-names"];
+    let scope_args = scope.rev_list_args();
+    let mut rev_list_args = vec!["rev-list", "--objects", "--no-object-names"];
+    rev_list_args.extend(scope_args.iter().map(String::as_str));
     let cat_file_args = [
         "cat-file",
-        "--batch-check='%(objecttype) %(objectname) %(objectsize) %(objectsize:disk)'",
+        "--batch-check='%(objecttype) %(objectname) %(objectsize) %(objectsize:disk) %(deltabase)'",
     ];
 
-    pipe_commands(repo_path, command, &rev_list_args, command, &cat_file_args)
+    pipe_commands_lines(
+        repo_path,
+        command,
+        &rev_list_args,
+        command,
+        &cat_file_args,
+        on_line,
+    )
 }
 
-pub fn get_tag_deps(repo_path: &Path) -> Result<String, String> {
+/// Stream the id of every object in `scope`, one line at a time.
+pub fn list_object_ids(
+    repo_path: &Path,
+    scope: &Scope,
+    on_line: &mut dyn FnMut(&str),
+) -> Result<()> {
+    let command = "git";
+    let scope_args = scope.rev_list_args();
+    let mut args = vec!["rev-list", "--objects", "--no-object-names"];
+    args.extend(scope_args.iter().map(String::as_str));
+
+    run_command_lines(repo_path, command, &args, on_line)
+}
+
+/// Get the deps of many commits with a small pool of long lived
+/// `git diff-tree --stdin` processes rather than one process per commit. The commits are
+/// split between `workers` processes and `on_deps` is called from the worker threads with
+/// each commit and its deps as soon as they have been read.
+///
+/// Each commit is compared against its first parent, so a merge lists everything brought in
+/// from the merged branch, much like `git rev-list <c>~1..<c>`.
+pub fn stream_commit_deps(
+    repo_path: &Path,
+    commits: &[String],
+    workers: usize,
+    on_deps: &(dyn Fn(&str, String) + Sync),
+) -> io::Result<()> {
+    if commits.is_empty() {
+        return Ok(());
+    }
+
+    let chunk_size = commits.len().div_ceil(workers.max(1));
+    thread::scope(|scope| {
+        let handles: Vec<_> = commits
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || diff_tree_worker(repo_path, chunk, on_deps)))
+            .collect();
+
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })
+}
+
+fn diff_tree_worker(
+    repo_path: &Path,
+    commits: &[String],
+    on_deps: &(dyn Fn(&str, String) + Sync),
+) -> io::Result<()> {
+    let mut child = Command::new("git")
+        .current_dir(repo_path)
+        .args([
+            "diff-tree",
+            "--stdin",
+            "-z",
+            "-r",
+            "-t",
+            "--root",
+            "--always",
+            "--diff-merges=first-parent",
+            "--format=%H %T",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .expect("Failed to get stdin of diff-tree");
+    let stdout = child
+        .stdout
+        .take()
+        .expect("Failed to get stdout of diff-tree");
+
+    // Feed the commits from their own thread so neither side of the pipe can block the other.
+    thread::scope(|scope| {
+        let writer = scope.spawn(move || -> io::Result<()> {
+            for commit_hash in commits {
+                writeln!(stdin, "{commit_hash}")?;
+            }
+            Ok(())
+        });
+
+        parse_diff_tree(BufReader::new(stdout), on_deps)?;
+        writer.join().unwrap()
+    })?;
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("git diff-tree failed: {status}")));
+    }
+    Ok(())
+}
+
+/// Parse the output of `git diff-tree --stdin -z -r -t --format='%H %T'` into the
+/// `git rev-list --objects` form used for commit deps. Every field is ended by a nul. Each
+/// commit starts with a `<commit> <root tree>` field, followed by a `:<modes> <old> <new>
+/// <status>` field and a path field for every changed tree and blob. Paths are passed through
+/// as they are, so they may hold any character other than a nul. Deleted entries and
+/// submodules are skipped, and the root tree is only listed when the commit changed
+/// something.
+pub fn parse_diff_tree(
+    mut reader: impl BufRead,
+    on_deps: &(dyn Fn(&str, String) + Sync),
+) -> io::Result<()> {
+    let mut current: Option<(String, String)> = None;
+    let mut deps = String::new();
+    let mut field = Vec::new();
+    // The info of a changed entry, waiting for the path that follows it.
+    let mut info: Option<String> = None;
+
+    loop {
+        field.clear();
+        if reader.read_until(b'\0', &mut field)? == 0 {
+            break;
+        }
+        if field.last() == Some(&b'\0') {
+            field.pop();
+        }
+        let text = String::from_utf8_lossy(&field);
+
+        if let Some(info) = info.take() {
+            let fields: Vec<&str> = info.split(' ').collect();
+            if fields.len() < 5 || fields[4] == "D" || fields[1] == "160000" {
+                continue;
+            }
+            push_dep(&mut deps, fields[3], &text);
+            continue;
+        }
+
+        // A commit with changes has a newline between its header and the first change.
+        let text = text.trim_start_matches('\n');
+        if let Some(diff) = text.strip_prefix(':') {
+            info = Some(diff.to_string());
+        } else if let Some((commit_hash, tree_hash)) = text.split_once(' ') {
+            if let Some((commit_hash, tree_hash)) = current.take() {
+                emit_diff_tree_deps(&commit_hash, &tree_hash, &mut deps, on_deps);
+            }
+            current = Some((commit_hash.to_string(), tree_hash.to_string()));
+        }
+    }
+
+    if let Some((commit_hash, tree_hash)) = current {
+        emit_diff_tree_deps(&commit_hash, &tree_hash, &mut deps, on_deps);
+    }
+    Ok(())
+}
+
+fn emit_diff_tree_deps(
+    commit_hash: &str,
+    tree_hash: &str,
+    deps: &mut String,
+    on_deps: &(dyn Fn(&str, String) + Sync),
+) {
+    let changes = std::mem::take(deps);
+    if changes.is_empty() {
+        on_deps(commit_hash, changes);
+    } else {
+        let mut commit_deps = String::with_capacity(tree_hash.len() + 2 + changes.len());
+        push_dep(&mut commit_deps, tree_hash, "");
+        commit_deps += &changes;
+        on_deps(commit_hash, commit_deps);
+    }
+}
+
+pub fn get_commit_parents(repo_path: &Path, scope: &Scope) -> Result<String> {
+    let command = "git";
+    let scope_args = scope.rev_list_args();
+    let mut args = vec!["rev-list", "--parents"];
+    args.extend(scope_args.iter().map(String::as_str));
+
+    run_command(repo_path, command, &args)
+}
+
+pub fn get_commit_metadata(repo_path: &Path, scope: &Scope) -> Result<String> {
+    let command = "git";
+    let scope_args = scope.rev_list_args();
+    let mut args = vec!["log", "--format=%H%x00%an%x00%ae%x00%at%x00%ct%x00%s"];
+    args.extend(scope_args.iter().map(String::as_str));
+
+    run_command(repo_path, command, &args)
+}
+
+pub fn get_tag_deps(repo_path: &Path) -> Result<String> {
     // git show-ref --tags -d
     let command = "git";
     let args = ["show-ref", "--tags", "-d"];
 
     run_command(repo_path, command, &args)
 }
+
+pub fn get_ref_tips(repo_path: &Path) -> Result<String> {
+    let command = "git";
+    let args = ["show-ref", "--head"];
+
+    run_command(repo_path, command, &args)
+}
+
+/// Reads the repo by running the git cli.
+pub struct GitCliSource {
+    repo_path: PathBuf,
+    scope: Scope,
+}
+
+impl GitCliSource {
+    pub fn new(repo_path: &Path) -> Self {
+        GitCliSource {
+            repo_path: repo_path.to_path_buf(),
+            scope: Scope::all(),
+        }
+    }
+
+    /// Only read the objects and commits in `scope`.
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+}
+
+impl ObjectSource for GitCliSource {
+    fn objects(&self, on_object: &mut dyn FnMut(ObjectRecord)) -> Result<()> {
+        // Make sure git runs and this is a repo first, otherwise a failure part way through the
+        // pipe would look like an empty repo.
+        run_command(&self.repo_path, "git", &["rev-parse", "--git-dir"])?;
+        list_objects(&self.repo_path, &self.scope, &mut |line: &str| {
+            if let Some(object) = parse_object_line(line) {
+                on_object(object);
+            }
+        })
+    }
+
+    fn commit_deps(&self, commit_hash: &str) -> Result<String> {
+        let deps = get_commit_deps(&self.repo_path, commit_hash)?;
+
+        // The first line is the commit itself. rev-list prints paths as they are, so escape
+        // them the same way as every other source.
+        let mut commit_deps = String::new();
+        for line in deps.lines().skip(1) {
+            let (hash, path) = line.split_once(' ').unwrap_or((line, ""));
+            push_dep(&mut commit_deps, hash, path);
+        }
+        Ok(commit_deps)
+    }
+
+    fn streams_commit_deps(&self) -> bool {
+        true
+    }
+
+    fn stream_commit_deps(
+        &self,
+        commits: &[String],
+        on_deps: &(dyn Fn(&str, String) + Sync),
+    ) -> Result<()> {
+        // Half the cpu count ( at least one ) to keep contention down, as with the per commit
+        // processing.
+        let workers = (num_cpus::get() / 2).max(1);
+        Ok(stream_commit_deps(
+            &self.repo_path,
+            commits,
+            workers,
+            on_deps,
+        )?)
+    }
+
+    fn commit_parents(&self) -> Result<String> {
+        get_commit_parents(&self.repo_path, &self.scope)
+    }
+
+    fn commit_metadata(&self) -> Result<String> {
+        get_commit_metadata(&self.repo_path, &self.scope)
+    }
+
+    fn tag_refs(&self) -> Result<String> {
+        match get_tag_deps(&self.repo_path) {
+            // show-ref exits with an error and says nothing when there are no tags.
+            Err(Error::CommandFailed { stderr, .. }) if stderr.is_empty() => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    fn ref_tips(&self) -> Result<String> {
+        match get_ref_tips(&self.repo_path) {
+            // as with tags, an empty repo has no refs to show.
+            Err(Error::CommandFailed { stderr, .. }) if stderr.is_empty() => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    fn repo_path(&self) -> Option<&Path> {
+        Some(&self.repo_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_processing::{
+        process_all_commit_deps, process_commit_parents, process_initial_repo, process_tags,
+    };
+    use crate::object_collection::ObjectContainer;
+    use crate::object_id::ObjectFormat;
+    use crate::object_source::Scope;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_diff_tree() {
+        let output = "\
+e0d97ed7ddeec9f3bb0c7f4746060fe7c0a86c2b a37f72996623fe2222cc4e9984092f93a42900a2\0\n\
+:040000 040000 aaff74984cccd156a469afa7d9ab10e4777beb24 3a247983d5372d3d195a08a8905eea1712cb881c M\0src\0\
+:000000 100644 0000000000000000000000000000000000000000 f2ad6c76f0115a6ba5b00456a849810e7ec0af20 A\0src/c\0\
+:100644 000000 61780798228d17af2d34fce4cfbdf35556832472 0000000000000000000000000000000000000000 D\0b\0\
+23039e597986046bda486c2ad7a251364ec7176e 3dadac0501f4cf5834b7c5aaa71ddc851ee948d3\0";
+        let deps = Mutex::new(Vec::new());
+        parse_diff_tree(
+            output.as_bytes(),
+            &|commit_hash: &str, commit_deps: String| {
+                deps.lock()
+                    .unwrap()
+                    .push((commit_hash.to_string(), commit_deps));
+            },
+        )
+        .unwrap();
+
+        let deps = deps.into_inner().unwrap();
+        assert_eq!(deps.len(), 2);
+        assert_eq!(deps[0].0, "e0d97ed7ddeec9f3bb0c7f4746060fe7c0a86c2b");
+        assert_eq!(
+            deps[0].1,
+            "a37f72996623fe2222cc4e9984092f93a42900a2 \n\
+             3a247983d5372d3d195a08a8905eea1712cb881c src\n\
+             f2ad6c76f0115a6ba5b00456a849810e7ec0af20 src/c\n"
+        );
+        // An empty commit has no deps.
+        assert_eq!(deps[1].1, "");
+    }
+
+    // Make an empty repo with a user set up, and return a helper to run git in it.
+    fn init_repo(repo: &Path) -> impl Fn(&[&str]) -> String + '_ {
+        let git = move |args: &[&str]| run_command(repo, "git", args).unwrap();
+        git(&["init", "-q", "-b", "main"]);
+        git(&["config", "user.email", "test@example.com"]);
+        git(&["config", "user.name", "Test"]);
+        git
+    }
+
+    #[tokio::test]
+    async fn test_git_cli_source_special_paths() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path();
+        let git = init_repo(repo);
+
+        // git quotes all of these without -z, and the newline would split the line.
+        let names = ["tab\there", "quote\"back\\slash", "new\nline"];
+        for (index, name) in names.iter().enumerate() {
+            fs::write(repo.join(name), format!("{index}\n")).unwrap();
+        }
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "special paths"]);
+
+        // Build the deps once to save them, then again to load them from the cache.
+        let deps_path = repo.join("deps.txt");
+        for _ in 0..2 {
+            let source: Arc<dyn ObjectSource> = Arc::new(GitCliSource::new(repo));
+            let mut container = ObjectContainer::new();
+            process_initial_repo(source.as_ref(), &mut container).unwrap();
+            process_all_commit_deps(source.clone(), &container, &Some(deps_path.clone()))
+                .await
+                .unwrap();
+
+            let mut paths: Vec<String> = container
+                .blobs()
+                .object_iter()
+                .flat_map(|blob| container.resolve_paths(blob.read().unwrap().paths()))
+                .collect();
+            paths.sort();
+            let mut expected: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            expected.sort();
+            assert_eq!(paths, expected);
+        }
+    }
+
+    #[test]
+    fn test_stream_commit_deps_merge() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path();
+        let git = init_repo(repo);
+
+        fs::write(repo.join("base.txt"), "base\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "base"]);
+        git(&["checkout", "-q", "-b", "side"]);
+        fs::write(repo.join("side.txt"), "side\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "side"]);
+        git(&["checkout", "-q", "main"]);
+        fs::write(repo.join("main.txt"), "main\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "main"]);
+        git(&["merge", "-q", "--no-ff", "--no-edit", "side"]);
+
+        let merge = git(&["rev-parse", "HEAD"]);
+        let merge_tree = git(&["rev-parse", "HEAD^{tree}"]);
+        let side_blob = git(&["rev-parse", "HEAD:side.txt"]);
+
+        let deps = Mutex::new(Vec::new());
+        stream_commit_deps(
+            repo,
+            std::slice::from_ref(&merge),
+            1,
+            &|commit_hash: &str, commit_deps| {
+                deps.lock()
+                    .unwrap()
+                    .push((commit_hash.to_string(), commit_deps));
+            },
+        )
+        .unwrap();
+
+        // Compared against the first parent, only what the merged branch brought in is listed.
+        let deps = deps.into_inner().unwrap();
+        assert_eq!(deps.len(), 1);
+        assert_eq!(deps[0].0, merge);
+        assert_eq!(deps[0].1, format!("{merge_tree} \n{side_blob} side.txt\n"));
+    }
+
+    #[test]
+    fn test_git_cli_source_not_a_repo() {
+        let dir = TempDir::new().unwrap();
+        let source = GitCliSource::new(dir.path());
+        let mut container = ObjectContainer::new();
+
+        let result = process_initial_repo(&source, &mut container);
+        assert!(matches!(result, Err(Error::NotARepo(_))));
+    }
+
+    #[test]
+    fn test_git_cli_source_scope() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path();
+        let git = |args: &[&str]| run_command(repo, "git", args).unwrap();
+        git(&["init", "-q", "-b", "main"]);
+        git(&["config", "user.email", "test@example.com"]);
+        git(&["config", "user.name", "Test"]);
+
+        for (index, branch) in ["main", "main", "release/1.0"].iter().enumerate() {
+            if index == 2 {
+                git(&["checkout", "-q", "-b", branch]);
+            }
+            fs::write(repo.join("file.txt"), format!("{index}\n")).unwrap();
+            git(&["add", "."]);
+            git(&["commit", "-q", "-m", &format!("commit {index}")]);
+            if index == 0 {
+                git(&["tag", "v1.0"]);
+            }
+        }
+
+        let count_commits = |scope: Scope| {
+            let source = GitCliSource::new(repo).with_scope(scope);
+            let mut container = ObjectContainer::new();
+            process_initial_repo(&source, &mut container).unwrap();
+            container.commits().count()
+        };
+
+        assert_eq!(count_commits(Scope::all()), 3);
+        assert_eq!(count_commits(Scope::new(&["main".into()], &[]).unwrap()), 2);
+        assert_eq!(
+            count_commits(Scope::new(&["release/*".into()], &[]).unwrap()),
+            3
+        );
+        assert_eq!(
+            count_commits(Scope::new(&[], &["v1.0..main".into()]).unwrap()),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_git_cli_source_sha256() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path();
+        let git = |args: &[&str]| run_command(repo, "git", args).unwrap();
+        git(&["init", "-q", "--object-format=sha256"]);
+        git(&["config", "user.email", "test@example.com"]);
+        git(&["config", "user.name", "Test"]);
+
+        fs::create_dir(repo.join("src")).unwrap();
+        fs::write(repo.join("src/main.rs"), "fn main() {}\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "initial"]);
+        fs::write(repo.join("README.md"), "readme\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "second"]);
+        git(&["tag", "-a", "v1.0", "-m", "release"]);
+        let head = git(&["rev-parse", "HEAD"]).trim().to_string();
+        assert_eq!(head.len(), ObjectFormat::Sha256.hex_len());
+
+        // Build the deps once to save them, then again to load them from the cache.
+        let deps_path = repo.join("deps.txt");
+        for _ in 0..2 {
+            let source: Arc<dyn ObjectSource> = Arc::new(GitCliSource::new(repo));
+            let mut container = ObjectContainer::new();
+            process_initial_repo(source.as_ref(), &mut container).unwrap();
+            process_all_commit_deps(source.clone(), &container, &Some(deps_path.clone()))
+                .await
+                .unwrap();
+            process_commit_parents(source.as_ref(), &container).unwrap();
+            process_tags(source.as_ref(), &container).unwrap();
+
+            assert_eq!(container.commits().count(), 2);
+            assert_eq!(container.blobs().count(), 2);
+            assert_eq!(container.tags().count(), 1);
+
+            let commit = container
+                .commits()
+                .get_by_hex(&head)
+                .unwrap()
+                .read()
+                .unwrap();
+            // the new root tree and README.md
+            assert_eq!(commit.tree_deps().len(), 1);
+            assert_eq!(commit.blob_deps().len(), 1);
+            assert_eq!(commit.parents().len(), 1);
+            assert_eq!(commit.tag_deps().len(), 1);
+
+            let blob = container.blobs().get_by_index(&commit.blob_deps()[0]);
+            let paths = container.resolve_paths(blob.read().unwrap().paths());
+            assert_eq!(paths, vec!["README.md"]);
+        }
+    }
+}
@@ -1,96 +1,239 @@
 use crate::blob::Blob;
-use crate::commit::Commit;
-use crate::git_commands::{get_commit_deps, get_tag_deps, list_objects};
-use crate::object_collection::{ObjectContainer, Properties};
+use crate::commit::{Commit, CommitMetadata};
+use crate::error::{Error, Result};
+use crate::object_collection::{BasicObjectContainer, Delta, ObjectContainer, Properties};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_source::{split_dep, ObjectKind, ObjectRecord, ObjectSource};
 use crate::tag::Tag;
 use crate::tree::Tree;
-use anyhow::Result;
 use rayon::prelude::*;
 use std::sync::{
-    atomic::{AtomicU32, AtomicU64, Ordering},
+    atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
     Arc, RwLock,
 };
 use std::{
     collections::HashMap,
     fs::File,
-    io::{BufRead, BufReader, ErrorKind, Write},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
     mem,
-    path::{Path, PathBuf},
+    path::PathBuf,
     time::{Duration, Instant},
 };
 use tokio::sync::{mpsc, Mutex, Semaphore};
 //use rayon::ThreadPoolBuilder;
 use tokio::task::JoinSet;
 
-pub fn process_initial_repo(repo_path: &Path, container: &mut ObjectContainer) {
-    // Get the list of all objects, their type and sizes from git. Then
-    // build up the initial set of in memory objects.
-    match list_objects(repo_path) {
-        Ok(result) => process_objects(&result, container),
-        Err(e) => eprintln!("Error: {e}"),
-    }
+pub fn process_initial_repo(
+    source: &dyn ObjectSource,
+    container: &mut ObjectContainer,
+) -> Result<()> {
+    // Get the list of all objects, their type and sizes from the source. Then
+    // build up the initial set of in memory objects. Objects are added as they are read, only
+    // the deltas are kept until the end so their chains can be worked out.
+    eprintln!("Processing objects...");
+    let mut deltas: Vec<ObjectRecord> = Vec::new();
+    source.objects(&mut |object: ObjectRecord| {
+        add_object(&object, container);
+        if object.delta_base.is_some() {
+            deltas.push(object);
+        }
+    })?;
+    add_delta_info(&deltas, container);
+    eprintln!("Done processing.");
 
-    println!("Added {} Commits.", container.commits().count());
-    println!("Added {} Trees.", container.trees().count());
-    println!("Added {} Blobs.", container.blobs().count());
-    println!("Added {} Tags.", container.tags().count());
+    eprintln!("Added {} Commits.", container.commits().count());
+    eprintln!("Added {} Trees.", container.trees().count());
+    eprintln!("Added {} Blobs.", container.blobs().count());
+    eprintln!("Added {} Tags.", container.tags().count());
+    Ok(())
 }
 
 // Given a list of objects their sizes and types in a single string with newlines for
 // each object. Build up the initial set of containers for each object type.
 pub fn process_objects(objects: &str, container: &mut ObjectContainer) {
-    println!("Processing objects...");
-    let object_lines = objects.lines();
-
-    //for line in object_lines {
-    for line in object_lines {
-        let object = line.replace('\'', "");
-        let properties: Vec<&str> = object.split(" ").collect();
-
-        // There may be a newline at the end of the data, so skip processing that line
-        if properties.len() == 4 {
-            let hash = properties[1];
-            let size = properties[2].parse::<u32>().unwrap();
-            let size_disk = properties[3].parse::<u32>().unwrap();
-
-            match properties[0] {
-                "commit" => {
-                    let index = container.commits().count();
-                    container
-                        .mut_commits()
-                        .add(hash, Commit::new(index, size, size_disk));
-                }
-                "blob" => {
-                    let index = container.blobs().count();
-                    container
-                        .mut_blobs()
-                        .add(hash, Blob::new(index, size, size_disk));
-                }
-                "tree" => {
-                    let index = container.trees().count();
-                    container
-                        .mut_trees()
-                        .add(hash, Tree::new(index, size, size_disk));
-                }
-                "tag" => {
-                    let index = container.tags().count();
-                    container
-                        .mut_tags()
-                        .add(hash, Tag::new(index, size, size_disk));
-                }
-                _ => println!("Unknown: {}", properties[0]),
+    add_objects(&parse_objects(objects), container);
+}
+
+/// Parse the output of `git cat-file --batch-check` in the form
+/// `'<type> <hash> <size> <disk size> [<delta base>]'` into a list of objects. A delta base
+/// of all zeros means the object is stored whole.
+pub fn parse_objects(objects: &str) -> Vec<ObjectRecord> {
+    objects.lines().filter_map(parse_object_line).collect()
+}
+
+/// Parse a single line of `git cat-file --batch-check` output, see `parse_objects`. Lines
+/// that are empty or malformed give None.
+pub fn parse_object_line(line: &str) -> Option<ObjectRecord> {
+    let object = line.trim_matches('\'');
+    let properties: Vec<&str> = object.split(" ").collect();
+
+    // There may be a newline at the end of the data, so skip processing that line
+    if properties.len() != 4 && properties.len() != 5 {
+        return None;
+    }
+
+    let delta_base = properties
+        .get(4)
+        .and_then(|base| ObjectId::from_hex(base))
+        .filter(|base| !base.is_null());
+    let Some(hash) = ObjectId::from_hex(properties[1]) else {
+        eprintln!("Invalid object id: {}", properties[1]);
+        return None;
+    };
+    let (Ok(size), Ok(size_disk)) = (properties[2].parse::<u64>(), properties[3].parse::<u64>())
+    else {
+        eprintln!("Invalid object size: {object}");
+        return None;
+    };
+
+    match ObjectKind::from_name(properties[0]) {
+        Some(kind) => Some(ObjectRecord {
+            kind,
+            hash,
+            size,
+            size_disk,
+            delta_base,
+        }),
+        None => {
+            eprintln!("Unknown: {}", properties[0]);
+            None
+        }
+    }
+}
+
+/// Build up the initial set of containers for each object type. Objects that are already
+/// in the container are skipped.
+pub fn add_objects(objects: &[ObjectRecord], container: &mut ObjectContainer) {
+    eprintln!("Processing objects...");
+
+    for object in objects {
+        add_object(object, container);
+    }
+    add_delta_info(objects, container);
+
+    eprintln!("Done processing.");
+}
+
+/// Add a single object to the container for its type, unless it is already there. Delta
+/// information is added separately once every object is in the container.
+pub fn add_object(object: &ObjectRecord, container: &mut ObjectContainer) {
+    let hash = object.hash;
+    let (size, size_disk) = (object.size, object.size_disk);
+
+    match object.kind {
+        ObjectKind::Commit => {
+            if container.commits().get_index(&hash).is_none() {
+                let index = container.commits().count();
+                container
+                    .mut_commits()
+                    .add(hash, Commit::new(index, size, size_disk));
+            }
+        }
+        ObjectKind::Blob => {
+            if container.blobs().get_index(&hash).is_none() {
+                let index = container.blobs().count();
+                container
+                    .mut_blobs()
+                    .add(hash, Blob::new(index, size, size_disk));
+            }
+        }
+        ObjectKind::Tree => {
+            if container.trees().get_index(&hash).is_none() {
+                let index = container.trees().count();
+                container
+                    .mut_trees()
+                    .add(hash, Tree::new(index, size, size_disk));
             }
         }
+        ObjectKind::Tag => {
+            if container.tags().get_index(&hash).is_none() {
+                let index = container.tags().count();
+                container
+                    .mut_tags()
+                    .add(hash, Tag::new(index, size, size_disk));
+            }
+        }
+    }
+}
+
+/// Record how each object is stored, linking deltas to their base and working out how long
+/// each delta chain is.
+fn add_delta_info(objects: &[ObjectRecord], container: &ObjectContainer) {
+    let bases: HashMap<ObjectId, ObjectId> = objects
+        .iter()
+        .filter_map(|o| o.delta_base.map(|base| (o.hash, base)))
+        .collect();
+    let mut depths: HashMap<ObjectId, u32> = HashMap::new();
+
+    for object in objects {
+        let Some(base) = &object.delta_base else {
+            continue;
+        };
+
+        let depth = delta_depth(object.hash, &bases, &mut depths);
+        let hash = &object.hash;
+        match object.kind {
+            ObjectKind::Commit => set_delta(container.commits(), hash, base, depth),
+            ObjectKind::Tree => set_delta(container.trees(), hash, base, depth),
+            ObjectKind::Blob => set_delta(container.blobs(), hash, base, depth),
+            ObjectKind::Tag => set_delta(container.tags(), hash, base, depth),
+        }
     }
+}
+
+// Follow the chain of bases until reaching an object stored whole, or one whose depth is
+// already known.
+fn delta_depth(
+    hash: ObjectId,
+    bases: &HashMap<ObjectId, ObjectId>,
+    depths: &mut HashMap<ObjectId, u32>,
+) -> u32 {
+    let mut chain: Vec<ObjectId> = Vec::new();
+    let mut current = hash;
+    let mut depth = loop {
+        if let Some(&depth) = depths.get(&current) {
+            break depth;
+        }
+        match bases.get(&current) {
+            // guard against a cycle in bad input
+            Some(&base) if chain.len() <= bases.len() => {
+                chain.push(current);
+                current = base;
+            }
+            _ => break 0,
+        }
+    };
+
+    for link in chain.into_iter().rev() {
+        depth += 1;
+        depths.insert(link, depth);
+    }
+    depth
+}
 
-    println!("Done processing.");
+fn set_delta<T: Properties + Delta>(
+    objects: &BasicObjectContainer<T>,
+    hash: &ObjectId,
+    base: &ObjectId,
+    depth: u32,
+) {
+    let base_index = objects.get_index(base).copied();
+    if let Some(object) = objects.get(hash) {
+        object.write().unwrap().set_delta(base_index, depth);
+    }
 }
 
 /// This is a wrapper function that will walk all the commits and build a list of just their hashes. This
 /// is done to allow for a faster set of processing and alleviate any issues with borrowing during
 /// processing.
+///
+/// Only commits whose deps have not been linked yet are processed, so a container loaded from
+/// a snapshot and then updated with the new objects in the repo only has the new commits
+/// read. Likewise any commit missing from an existing deps file is read from the source and
+/// added to the file, as happens once the refs have moved since it was saved. A deps file
+/// built for another repo, by another version of the tool or in an older format is rebuilt.
 pub async fn process_all_commit_deps(
-    repo_path: &Path,
+    source: Arc<dyn ObjectSource>,
     container: &ObjectContainer,
     save_load_deps: &Option<PathBuf>,
 ) -> Result<()> {
@@ -100,50 +243,132 @@ pub async fn process_all_commit_deps(
         container
             .commits()
             .object_hash_iter()
-            .for_each(|(hash, _index): (&String, &usize)| {
-                commits.push(hash.clone());
+            .for_each(|(hash, index): (&ObjectId, &usize)| {
+                let commit = container.commits().get_by_index(index).read().unwrap();
+                if !commit.deps_processed() {
+                    commits.push(hash.to_string());
+                }
             });
     }
 
-    let commit_deps: HashMap<String, String>;
-
     // If we have been asked to save/load the processed deps to save on
     // building those out which can be time consuming.
     if save_load_deps.is_some() {
         // check for an already existing deps file, if we have one load from there.
         // otherwise we save at the end of processing.
         let save_load_path = save_load_deps.as_ref().unwrap();
+        let header = DepsHeader::for_source(source.as_ref(), container)?;
 
         // If we already have a saved file, just load the deps into memory for processing.
+        let mut existing: Option<(DepsHeader, HashMap<String, String>)> = None;
         if save_load_path.exists() {
-            commit_deps = load_deps(save_load_path)?;
+            existing = load_deps(save_load_path, &header)?;
+        }
+
+        if let Some((saved_header, mut commit_deps)) = existing {
+            process_commit_deps(&commit_deps, container);
+            if saved_header.refs_hash != header.refs_hash {
+                eprintln!("Refs have moved since the deps file was saved");
+            }
+
+            // Commits made since the file was saved still need their deps read, add them
+            // to the file for next time. The file is saved again when the refs moved, even
+            // with no new commits, so the next run sees it as up to date.
+            commits.retain(|commit_hash| !commit_deps.contains_key(commit_hash));
+            if !commits.is_empty() {
+                eprintln!(
+                    "{} commits are not in the deps file, adding them",
+                    commits.len()
+                );
+                commit_deps.extend(build_deps(source, &commits, container).await?);
+            }
+            if !commits.is_empty() || saved_header.refs_hash != header.refs_hash {
+                save_deps(&header, &commit_deps, save_load_path)?;
+            }
         } else {
             // Otherwise we need to build the deps first then save them out to file.
-            commit_deps = build_deps_tokio(repo_path, &commits).await;
-            save_deps(&commit_deps, save_load_path)?;
+            let commit_deps = build_deps(source, &commits, container).await?;
+            save_deps(&header, &commit_deps, save_load_path)?;
         }
     } else {
         // No load/save action requested, just build the deps.
-        commit_deps = build_deps_tokio(repo_path, &commits).await;
+        build_deps(source, &commits, container).await?;
     }
 
+    Ok(())
+}
+
+/// Build the deps for every commit and connect them to the objects in the container. Sources
+/// that can stream deps for many commits at once are processed as the deps are read, others
+/// are read one commit at a time in parallel and processed at the end.
+async fn build_deps(
+    source: Arc<dyn ObjectSource>,
+    commits: &[String],
+    container: &ObjectContainer,
+) -> Result<HashMap<String, String>> {
+    if commits.is_empty() {
+        return Ok(HashMap::new());
+    }
+    if source.streams_commit_deps() {
+        return build_deps_streaming(source.as_ref(), commits, container);
+    }
+
+    let commit_deps = build_deps_tokio(source, commits).await;
     process_commit_deps(&commit_deps, container);
+    Ok(commit_deps)
+}
 
-    Ok(())
+/// Stream the deps of all commits from the source, processing each commit as soon as its deps
+/// have been read rather than waiting for the whole set.
+fn build_deps_streaming(
+    source: &dyn ObjectSource,
+    commits: &[String],
+    container: &ObjectContainer,
+) -> Result<HashMap<String, String>> {
+    let start = Instant::now();
+    eprintln!("Streaming commit deps...");
+
+    let total_commits = commits.len().max(1);
+    let progress = AtomicUsize::new(0);
+    let commit_deps = std::sync::Mutex::new(HashMap::new());
+
+    source.stream_commit_deps(commits, &|commit_hash: &str, deps: String| {
+        process_single_commit_deps(commit_hash, &deps, container);
+        // Commits with no deps are kept too, so the deps file records that they were read.
+        commit_deps
+            .lock()
+            .unwrap()
+            .insert(commit_hash.to_string(), deps);
+
+        let completed = progress.fetch_add(1, Ordering::Relaxed) + 1;
+        if (completed * 100) / total_commits > ((completed - 1) * 100) / total_commits {
+            eprintln!(
+                "Progress: {}% ({completed} of {total_commits}), in {:.2?}",
+                (completed * 100) / total_commits,
+                start.elapsed()
+            );
+        }
+    })?;
+
+    eprintln!("\rDone getting deps in {:?}", start.elapsed());
+    Ok(commit_deps.into_inner().unwrap())
 }
 
 /// Build a HashMap of commit hash to dependencies. Where dependencies is a string representing
 /// all objects tied to that single commit.
 /// Note on processing times. This can take quite a while on a large repo anywhere from 10 min to an hour.
 /// Debug and progress information is printed to the console to give an idea of progress.
-async fn build_deps_tokio(repo_path: &Path, commits: &[String]) -> HashMap<String, String> {
+async fn build_deps_tokio(
+    source: Arc<dyn ObjectSource>,
+    commits: &[String],
+) -> HashMap<String, String> {
     let start = Instant::now();
-    println!(
-        "Getting commit deps. Runs a git command for every commit (This could take a while)..."
+    eprintln!(
+        "Getting commit deps. Reads the deps of every commit from the source (This could take a while)..."
     );
 
-    // Just use have the cpu count to keep contention down. Could be a param on the CLI or read from a .env
-    let num_cpus = num_cpus::get() / 2;
+    // Just use half the cpu count ( at least one ) to keep contention down. Could be a param on the CLI or read from a .env
+    let num_cpus = (num_cpus::get() / 2).max(1);
     let semaphore = Arc::new(Semaphore::new(num_cpus)); // limit the number of concurrent tasks
 
     let mut set = JoinSet::new();
@@ -196,7 +421,7 @@ async fn build_deps_tokio(repo_path: &Path, commits: &[String]) -> HashMap<Strin
                     };
 
                     last_reported.store(progress_percent, Ordering::Relaxed);
-                    println!(
+                    eprintln!(
                         "Progress: {progress_percent}% ({completed} of {total_commits}), Avg: {avg_time:.2?}/task, in {percent_time:.2?}", 
                     );
                 }
@@ -213,21 +438,16 @@ async fn build_deps_tokio(repo_path: &Path, commits: &[String]) -> HashMap<Strin
         let completed_count = completed_count.clone();
 
         let commit_hash = commit_hash.to_string();
-        let repo_path = repo_path.to_path_buf();
+        let source = source.clone();
 
         set.spawn_blocking(move || {
             let start = Instant::now();
 
-            let deps = match get_commit_deps(&repo_path, &commit_hash) {
-                Ok(value) => value,
-                Err(_) => "".to_string(),
-            };
-
+            // A commit that fails to read is left out, so it is read again next time rather
+            // than saved as having no deps.
             let mut commit_deps = HashMap::new();
-
-            if let Some(index) = deps.find('\n') {
-                let updated_deps = &deps[index + 1..];
-                commit_deps.insert(commit_hash, updated_deps.to_string());
+            if let Ok(deps) = source.commit_deps(&commit_hash) {
+                commit_deps.insert(commit_hash, deps);
             }
 
             // Update progress and timing
@@ -251,192 +471,496 @@ async fn build_deps_tokio(repo_path: &Path, commits: &[String]) -> HashMap<Strin
     let mut final_deps = HashMap::new();
 
     // For showing a progress indicator
-    println!("Merging results of {} tasks...", set.len());
+    eprintln!("Merging results of {} tasks...", set.len());
     while let Some(result) = set.join_next().await {
         if let Ok(hashmap) = result {
             final_deps.extend(hashmap);
         }
     }
 
-    println!("\rDone getting deps in {:?}", start.elapsed());
+    eprintln!("\rDone getting deps in {:?}", start.elapsed());
     final_deps
 }
 
+// The first line of every deps file, followed by the format version.
+const DEPS_MAGIC: &str = "git-dag-analyzer deps";
+const DEPS_VERSION: u32 = 5;
+
+/// Identifies what a deps file was built from, so a file for another repo, or one written by
+/// another version of the tool, is not used by mistake.
+///
+/// A change to the refs does not make a file unusable. The deps of a commit never change, so
+/// a file saved before new commits were made or fetched is still right for the commits it
+/// holds, and the missing ones are read and added to it rather than the whole file being
+/// rebuilt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepsHeader {
+    pub tool_version: String,
+    pub repo_path: String,
+    pub object_format: ObjectFormat,
+    /// A checksum of every ref and what it points at. Differs once anything new has been
+    /// committed or fetched.
+    pub refs_hash: u32,
+}
+
+impl DepsHeader {
+    /// The header for a deps file built from `source` now.
+    pub fn for_source(source: &dyn ObjectSource, container: &ObjectContainer) -> Result<Self> {
+        let repo_path = match source.repo_path() {
+            Some(path) => path
+                .canonicalize()
+                .unwrap_or_else(|_| path.to_path_buf())
+                .display()
+                .to_string(),
+            None => String::new(),
+        };
+        let object_format = container
+            .commits()
+            .lookup_hash_for_index(&0)
+            .map(ObjectId::format)
+            .unwrap_or_default();
+
+        // Sorted so the hash does not depend on the order the source lists refs in.
+        let ref_tips = source.ref_tips()?;
+        let mut refs: Vec<&str> = ref_tips.lines().collect();
+        refs.sort_unstable();
+        let mut hasher = crc32fast::Hasher::new();
+        for line in refs {
+            hasher.update(line.as_bytes());
+            hasher.update(b"\n");
+        }
+
+        Ok(DepsHeader {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            repo_path,
+            object_format,
+            refs_hash: hasher.finalize(),
+        })
+    }
+
+    /// Why a file with this header can not be used in place of one with `expected`, None if
+    /// it can. A change to the refs is not a mismatch, the missing commits are added instead.
+    fn mismatch(&self, expected: &DepsHeader) -> Option<String> {
+        if self.tool_version != expected.tool_version {
+            Some(format!("written by version {}", self.tool_version))
+        } else if self.repo_path != expected.repo_path {
+            Some(format!("built for {}", self.repo_path))
+        } else if self.object_format != expected.object_format {
+            Some(format!("built for a {} repo", self.object_format.name()))
+        } else {
+            None
+        }
+    }
+}
+
 /// If specified we will load the data to a file for later processing. The point of this is to
 /// save on processing time if are running the commands more than once. Mainly for debugging
 /// purposes.
-fn load_deps(load_path: &PathBuf) -> Result<HashMap<String, String>> {
-    println!("Loading commit deps from file: {load_path:?}");
+///
+/// The file must start with a header and end with a checksum of everything before it. The
+/// header is returned along with the deps, so the caller can tell if the refs have moved. None
+/// if the file has no header, was written in an older format, or its header does not match
+/// `expected`, so the caller rebuilds it. A file that is truncated or edited is rejected with
+/// `Error::CacheCorrupt`.
+fn load_deps(
+    load_path: &PathBuf,
+    expected: &DepsHeader,
+) -> Result<Option<(DepsHeader, HashMap<String, String>)>> {
+    eprintln!("Loading commit deps from file: {load_path:?}");
     let start = Instant::now();
     let mut deps: HashMap<String, String> = HashMap::new();
     let file = File::open(load_path)?;
-    let reader = BufReader::new(file);
+    let mut reader = BufReader::new(file);
+    let mut hasher = crc32fast::Hasher::new();
 
     let mut have_hash = false;
     let mut hash: String = "".to_string();
     let mut dep_lines = String::new();
+    let corrupt = |reason: String| Error::CacheCorrupt {
+        path: load_path.clone(),
+        reason,
+    };
+
+    // Read a line along with its newline, adding it to the checksum. None at the end of
+    // the file.
+    let mut read_line = |hasher: &mut crc32fast::Hasher| -> Result<Option<String>> {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                hasher.update(line.as_bytes());
+                if line.ends_with('\n') {
+                    line.pop();
+                }
+                Ok(Some(line))
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidData => Err(corrupt(e.to_string())),
+            Err(e) => Err(e.into()),
+        }
+    };
+
+    // A file from before there was a header, or in an older format, is rebuilt.
+    let first_line = read_line(&mut hasher)?.unwrap_or_default();
+    let outdated = match first_line.strip_prefix(DEPS_MAGIC) {
+        None => Some("written before deps files had a header".to_string()),
+        Some(version) if version.trim_start() != DEPS_VERSION.to_string() => {
+            Some(format!("in deps file format {}", version.trim_start()))
+        }
+        Some(_) => None,
+    };
+    if let Some(reason) = outdated {
+        eprintln!("Deps file was {reason}, rebuilding it");
+        return Ok(None);
+    }
+
+    let header = read_deps_header(&mut || read_line(&mut hasher)).map_err(corrupt)?;
+    if let Some(reason) = header.mismatch(expected) {
+        eprintln!("Deps file was {reason}, rebuilding it");
+        return Ok(None);
+    }
 
     // walk the lines from the file. Once we have a semi colon the next line is a
     // hash. After the has each line is a dep until we see another semi colon, and
-    // the process starts over.
-    for line_result in reader.lines() {
-        match line_result {
-            Ok(line) => {
-                if line.eq(";") {
-                    // look for a semi colon if we find one the next line is the hash
-                    if !dep_lines.is_empty() {
-                        deps.insert(hash.to_string(), mem::take(&mut dep_lines));
-                        dep_lines.clear();
-                    }
-                    have_hash = true;
-                } else if have_hash {
-                    hash = line;
-                    have_hash = false;
-                } else {
-                    if line.ends_with(" ") {
-                        dep_lines += &line[..line.len() - 1];
-                    } else {
-                        dep_lines += &line;
-                    }
-
-                    dep_lines += "\n";
-                }
+    // the process starts over. The checksum line ends the file.
+    let mut checksum_hasher = hasher.clone();
+    let mut checksum: Option<String> = None;
+    while let Some(line) = read_line(&mut hasher)? {
+        if checksum.is_some() {
+            return Err(corrupt("data after the checksum".to_string()));
+        }
+        if let Some(value) = line.strip_prefix("checksum ") {
+            checksum = Some(value.to_string());
+        } else if line.eq(";") {
+            // look for a semi colon if we find one the next line is the hash. A hash
+            // with no deps is kept, it records that the commit has been read.
+            if !hash.is_empty() {
+                deps.insert(mem::take(&mut hash), mem::take(&mut dep_lines));
             }
-            Err(e) => match e.kind() {
-                ErrorKind::UnexpectedEof => {
-                    break;
-                }
-                _ => return Err(e.into()),
-            },
+            have_hash = true;
+        } else if have_hash {
+            if ObjectId::from_hex(&line).is_none() {
+                return Err(corrupt(format!("invalid commit hash: {line}")));
+            }
+            hash = line;
+            have_hash = false;
+        } else {
+            // every dep starts with the id of a tree or blob, and must follow a commit.
+            let dep_hash = line.split(' ').next().unwrap_or_default();
+            if hash.is_empty() || ObjectId::from_hex(dep_hash).is_none() {
+                return Err(corrupt(format!("invalid dep: {line}")));
+            }
+            if line.ends_with(" ") {
+                dep_lines += &line[..line.len() - 1];
+            } else {
+                dep_lines += &line;
+            }
+
+            dep_lines += "\n";
+        }
+        // The checksum covers every line before the checksum line itself.
+        if checksum.is_none() {
+            checksum_hasher = hasher.clone();
         }
     }
 
-    println!("\rDone loading deps in {:?}", start.elapsed());
-    Ok(deps)
+    match checksum {
+        None => return Err(corrupt("no checksum, the file is truncated".to_string())),
+        Some(value) if value != format!("{:08x}", checksum_hasher.finalize()) => {
+            return Err(corrupt("checksum does not match".to_string()));
+        }
+        Some(_) => {}
+    }
+
+    eprintln!("\rDone loading deps in {:?}", start.elapsed());
+    Ok(Some((header, deps)))
+}
+
+// Parse the header lines that follow the format line of a deps file, up to the first semi
+// colon.
+fn read_deps_header(
+    read_line: &mut dyn FnMut() -> Result<Option<String>>,
+) -> std::result::Result<DepsHeader, String> {
+    let mut next = |name: &str| -> std::result::Result<String, String> {
+        match read_line() {
+            Ok(Some(line)) => line
+                .strip_prefix(name)
+                .and_then(|value| value.strip_prefix(' '))
+                .map(str::to_string)
+                .ok_or_else(|| format!("expected {name} in the header")),
+            Ok(None) => Err("the header is truncated".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    };
+
+    let tool_version = next("tool")?;
+    let repo_path = next("repo")?;
+    let object_format = next("format")?;
+    let object_format = ObjectFormat::from_name(&object_format)
+        .ok_or_else(|| format!("unknown object format {object_format}"))?;
+    let refs_hash = next("refs")?;
+    let refs_hash = u32::from_str_radix(&refs_hash, 16)
+        .map_err(|_| format!("invalid refs hash {refs_hash}"))?;
+
+    Ok(DepsHeader {
+        tool_version,
+        repo_path,
+        object_format,
+        refs_hash,
+    })
 }
 
 /// If specified we will save the data to a file for later consumption. The point of this is to
 /// save on processing time if are running the commands more than once. Mainly for debugging
 /// purposes.
-fn save_deps(commit_deps: &HashMap<String, String>, save_path: &PathBuf) -> Result<()> {
+///
+/// The file is written alongside and then moved into place, so a run that is interrupted
+/// never leaves a partly written file behind.
+fn save_deps(
+    header: &DepsHeader,
+    commit_deps: &HashMap<String, String>,
+    save_path: &PathBuf,
+) -> Result<()> {
+    let mut temp_path = save_path.clone().into_os_string();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
     // open the file for writing.
-    let mut file = File::create(save_path)?;
+    let mut file = BufWriter::new(File::create(&temp_path)?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut write = |bytes: &[u8]| -> Result<()> {
+        hasher.update(bytes);
+        file.write_all(bytes)?;
+        Ok(())
+    };
+
+    write(
+        format!(
+            "{DEPS_MAGIC} {DEPS_VERSION}\ntool {}\nrepo {}\nformat {}\nrefs {:08x}\n",
+            header.tool_version,
+            header.repo_path,
+            header.object_format.name(),
+            header.refs_hash
+        )
+        .as_bytes(),
+    )?;
+
     // write a semi colon as a commit delimiter.
-    file.write_all(b";\n")?;
+    write(b";\n")?;
     commit_deps
         .iter()
         .try_for_each(|(commit_hash, deps)| -> Result<()> {
             // write the commit hash
-            file.write_all(format!("{commit_hash}\n").as_bytes())?;
+            write(format!("{commit_hash}\n").as_bytes())?;
 
             // write the deps ( already have \n )
-            file.write_all(deps.as_bytes())?;
+            write(deps.as_bytes())?;
 
-            if !deps.ends_with("\n") {
-                file.write_all(b"\n")?;
+            if !deps.is_empty() && !deps.ends_with("\n") {
+                write(b"\n")?;
             }
 
             // write a semi colon for next hash.
-            file.write_all(b";\n")?;
+            write(b";\n")
+        })?;
 
-            Ok(())
-        })
+    file.write_all(format!("checksum {:08x}\n", hasher.finalize()).as_bytes())?;
+    file.flush()?;
+    drop(file);
+    std::fs::rename(&temp_path, save_path)?;
+    Ok(())
 }
 
 /// Given a set of existing commits and their depended set. Walk them and build the
 /// connections between objects.
 pub fn process_commit_deps(commit_deps: &HashMap<String, String>, container: &ObjectContainer) {
-    println!("Processing commit deps...");
+    eprintln!("Processing commit deps...");
     let start = Instant::now();
 
     // Walk all the collected dep strings in parallel
     commit_deps.par_iter().for_each(|(commit_hash, deps)| {
-        if let Some(commit_res) = container.commits().get(commit_hash) {
-            let mut commit = commit_res.write().unwrap();
-
-            let dep_lines = deps.lines();
-            for line in dep_lines {
-                if line.len() < 40 {
-                    // There may have been a newline at the end of the dep_lines. This
-                    // causes there to be an empty item in the list ( line ). Just
-                    // ignore it.
-                    continue;
-                }
+        process_single_commit_deps(commit_hash, deps, container);
+    });
 
-                let hash = &line[..40].to_string();
-                let mut path = ""; // we can have a tree with no path ( root tree )
-                if line.len() > 41 {
-                    path = &line[41..];
-                }
+    eprintln!("processed all commit deps in: {:?}", start.elapsed())
+}
 
-                // try to get this as a tree, failing that its a blob. If its a commit we can
-                // skip it as the data is in the deps.
-                let op_tree_index = container.trees().get_index(hash);
-                match op_tree_index {
-                    Some(tree_index) => {
-                        if let Some(tree) = container.trees().get(hash) {
-                            let mut tree_guard = tree.write().unwrap();
-                            tree_guard.add_path(path);
-                            tree_guard.add_commit(commit.hash_index());
-                            commit.add_tree_dep(tree_index);
-                        } else {
-                            println!("Unable to find tree: {hash}");
-                        }
+/// Connect a single commit to the trees and blobs listed in its deps.
+pub fn process_single_commit_deps(commit_hash: &str, deps: &str, container: &ObjectContainer) {
+    if let Some(commit_res) = container.commits().get_by_hex(commit_hash) {
+        let mut commit = commit_res.write().unwrap();
+        // Already linked, from an earlier run loaded from a snapshot.
+        if commit.deps_processed() {
+            return;
+        }
+        commit.set_deps_processed();
+
+        let dep_lines = deps.lines();
+        for line in dep_lines {
+            // we can have a tree with no path ( root tree ). The id is either sha1 or sha256
+            // so split on the space rather than assuming a length.
+            let (hash, path) = split_dep(line);
+            if hash.is_empty() {
+                // There may have been a newline at the end of the dep_lines. This
+                // causes there to be an empty item in the list ( line ). Just
+                // ignore it.
+                continue;
+            }
+
+            let Some(hash) = ObjectId::from_hex(hash) else {
+                eprintln!("Invalid object id in deps: {line}");
+                continue;
+            };
+            let hash = &hash;
+            let path_id = container.intern_path(&path);
+
+            // try to get this as a tree, failing that its a blob. If its a commit we can
+            // skip it as the data is in the deps.
+            let op_tree_index = container.trees().get_index(hash);
+            match op_tree_index {
+                Some(tree_index) => {
+                    if let Some(tree) = container.trees().get(hash) {
+                        let mut tree_guard = tree.write().unwrap();
+                        tree_guard.add_path(path_id);
+                        tree_guard.add_commit(commit.hash_index());
+                        commit.add_tree_dep(tree_index);
+                    } else {
+                        eprintln!("Unable to find tree: {hash}");
                     }
-                    None => {
-                        let op_blob_index = container.blobs().get_index(hash);
-                        match op_blob_index {
-                            Some(blob_index) => {
-                                if let Some(blob) = container.blobs().get(hash) {
-                                    let mut blob_guard = blob.write().unwrap();
-                                    blob_guard.add_path(path);
-                                    blob_guard.add_commit(commit.hash_index());
-                                    commit.add_blob_dep(blob_index);
-                                } else {
-                                    println!("Unable to find blob: {hash}");
-                                }
-                            }
-                            None => {
-                                // this is a commit object and we can skip it.
+                }
+                None => {
+                    let op_blob_index = container.blobs().get_index(hash);
+                    match op_blob_index {
+                        Some(blob_index) => {
+                            if let Some(blob) = container.blobs().get(hash) {
+                                let mut blob_guard = blob.write().unwrap();
+                                blob_guard.add_path(path_id);
+                                blob_guard.add_commit(commit.hash_index());
+                                commit.add_blob_dep(blob_index);
+                            } else {
+                                eprintln!("Unable to find blob: {hash}");
                             }
                         }
+                        None => {
+                            // this is a commit object and we can skip it.
+                        }
                     }
                 }
             }
         }
-    });
+    }
+}
+
+/// Link every commit to its parents, and each parent back to its children, so the commit
+/// DAG can be walked.
+pub fn process_commit_parents(
+    source: &dyn ObjectSource,
+    container: &ObjectContainer,
+) -> Result<()> {
+    eprintln!("Processing commit parents...");
+    let start = Instant::now();
+
+    let commit_parents = source.commit_parents()?;
+    // Parents outside the scope, or missing from a shallow clone, are only counted. A range
+    // can leave thousands of them, too many to list one by one.
+    let mut missing = 0;
+
+    for line in commit_parents.lines() {
+        let mut hashes = line.split(' ');
+        let Some(commit_index) = hashes
+            .next()
+            .and_then(|h| container.commits().get_index_by_hex(h))
+        else {
+            continue;
+        };
+
+        let mut commit = container
+            .commits()
+            .get_by_index(commit_index)
+            .write()
+            .unwrap();
+        if !commit.parents().is_empty() {
+            // already linked, rev-list only lists a commit once but be safe.
+            continue;
+        }
+
+        for parent_hash in hashes {
+            match container.commits().get_index_by_hex(parent_hash) {
+                Some(parent_index) if parent_index != commit_index => {
+                    commit.add_parent(parent_index);
+                    container
+                        .commits()
+                        .get_by_index(parent_index)
+                        .write()
+                        .unwrap()
+                        .add_child(commit_index);
+                }
+                _ => missing += 1,
+            }
+        }
+    }
 
-    println!("processed all commit deps in: {:?}", start.elapsed())
+    if missing > 0 {
+        eprintln!("{missing} parent commits were not found, they are outside the scope");
+    }
+    eprintln!("Done processing commit parents in: {:?}", start.elapsed());
+    Ok(())
 }
 
-pub fn process_tags(repo_path: &Path, container: &ObjectContainer) {
-    println!("Processing tags...");
+/// Attach the author, dates and subject line to every commit. Names, emails and subjects are
+/// interned in the container as they repeat across many commits.
+pub fn process_commit_metadata(
+    source: &dyn ObjectSource,
+    container: &ObjectContainer,
+) -> Result<()> {
+    eprintln!("Processing commit metadata...");
     let start = Instant::now();
 
-    let tag_deps = match get_tag_deps(repo_path) {
-        Ok(result) => result,
-        Err(e) => {
-            println!("Unable to get tag deps. Error: {e}");
-            return;
+    let commit_metadata = source.commit_metadata()?;
+
+    for line in commit_metadata.lines() {
+        let fields: Vec<&str> = line.splitn(6, '\0').collect();
+        if fields.len() != 6 {
+            return Err(Error::malformed("commit metadata", line));
         }
-    };
+
+        let Some(commit) = container.commits().get_by_hex(fields[0]) else {
+            continue;
+        };
+
+        let metadata = CommitMetadata {
+            author_name: container.intern(fields[1]),
+            author_email: container.intern(fields[2]),
+            author_time: fields[3].parse().unwrap_or_default(),
+            commit_time: fields[4].parse().unwrap_or_default(),
+            subject: container.intern(fields[5]),
+        };
+        commit.write().unwrap().set_metadata(metadata);
+    }
+
+    eprintln!("Done processing commit metadata in: {:?}", start.elapsed());
+    Ok(())
+}
+
+pub fn process_tags(source: &dyn ObjectSource, container: &ObjectContainer) -> Result<()> {
+    eprintln!("Processing tags...");
+    let start = Instant::now();
+
+    let tag_deps = source.tag_refs()?;
 
     let lines = tag_deps.lines();
-    println!("Processing tag items...");
+    eprintln!("Processing tag items...");
     let mut previous_tag: Option<&RwLock<Tag>> = None;
     for line in lines {
-        let deps: Vec<&str> = line.split(" ").collect();
-
-        let hash = deps[0];
-        let label = deps[1];
+        let Some((hash, label)) = line.split_once(' ') else {
+            return Err(Error::malformed("tag ref", line));
+        };
 
         // first see if this is a commit
-        let commit_index = container.commits().get_index(hash);
-        if commit_index.is_some() {
+        if let Some(commit_index) = container.commits().get_index_by_hex(hash) {
             // this is a commit
             let mut commit = container
                 .commits()
-                .get_by_index(commit_index.unwrap())
+                .get_by_index(commit_index)
                 .write()
                 .unwrap();
 
@@ -452,28 +976,548 @@ pub fn process_tags(repo_path: &Path, container: &ObjectContainer) {
                 } else {
                     let hash = container.tags().lookup_hash_for_index(tag.hash_index());
                     match hash {
-                        Some(h) => println!("Tag found with no related commit: {h}"),
-                        None => println!("Tag found with no related commit, tag hash not found"),
+                        Some(h) => eprintln!("Tag found with no related commit: {h}"),
+                        None => eprintln!("Tag found with no related commit, tag hash not found"),
                     };
                 }
                 previous_tag = None;
-            } else {
+            } else if !label.ends_with("^{}") {
                 // There is no Tag object tied to this commit.
                 // Just a lightweight tag/label
                 commit.add_lightweight_tag(label);
             }
         } else {
             // this is a tag
-            let tag_opt = container.tags().get(hash);
+            let tag_opt = container.tags().get_by_hex(hash);
             if let Some(tag) = tag_opt {
                 // this is a tag object
                 let mut tag_guard = tag.write().unwrap();
                 tag_guard.add_name(label);
+                previous_tag = Some(tag);
             } else {
-                println!("Unable to find tag: {hash}");
+                // The ref points outside the container, such as a tag outside the scope being
+                // read, skip it along with the commit line that follows an annotated tag.
+                previous_tag = None;
             }
         }
     }
 
-    println!("Done processing tags in: {:?}", start.elapsed());
+    eprintln!("Done processing tags in: {:?}", start.elapsed());
+    Ok(())
+}
+
+/// Record which commit every ref points at, including `HEAD`. Annotated tags are followed to
+/// their commit, so `process_tags` must be run first for them to be included. Refs pointing
+/// outside the container are skipped.
+pub fn process_refs(source: &dyn ObjectSource, container: &ObjectContainer) -> Result<()> {
+    eprintln!("Processing refs...");
+    let start = Instant::now();
+
+    let ref_tips = source.ref_tips()?;
+    let mut refs: Vec<(String, usize)> = Vec::new();
+    for line in ref_tips.lines() {
+        let Some((hash, name)) = line.split_once(' ') else {
+            return Err(Error::malformed("ref", line));
+        };
+
+        let commit_index = match container.commits().get_index_by_hex(hash) {
+            Some(index) => Some(*index),
+            None => container
+                .tags()
+                .get_by_hex(hash)
+                .and_then(|tag| tag.read().unwrap().commit_dep()),
+        };
+        if let Some(commit_index) = commit_index {
+            refs.push((name.to_string(), commit_index));
+        }
+    }
+    container.set_refs(refs);
+
+    eprintln!("Done processing refs in: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_source::InMemorySource;
+    use crate::report_commits::calc_commit_size;
+
+    const COMMIT: &str = "1111111111111111111111111111111111111111";
+    const TREE: &str = "2222222222222222222222222222222222222222";
+    const SUB_TREE: &str = "3333333333333333333333333333333333333333";
+    const BLOB: &str = "4444444444444444444444444444444444444444";
+    const OTHER_BLOB: &str = "5555555555555555555555555555555555555555";
+    const TAG: &str = "6666666666666666666666666666666666666666";
+
+    fn build_source() -> InMemorySource {
+        let mut source = InMemorySource::new();
+        source.add_object(ObjectKind::Commit, COMMIT, 200, 150);
+        source.add_object(ObjectKind::Tree, TREE, 100, 80);
+        source.add_object(ObjectKind::Tree, SUB_TREE, 50, 40);
+        source.add_object(ObjectKind::Blob, BLOB, 1000, 500);
+        source.add_object(ObjectKind::Blob, OTHER_BLOB, 20, 20);
+        source.add_object(ObjectKind::Tag, TAG, 140, 130);
+
+        source.add_commit_dep(COMMIT, TREE, "");
+        source.add_commit_dep(COMMIT, SUB_TREE, "src");
+        source.add_commit_dep(COMMIT, BLOB, "src/main.rs");
+
+        source.add_tag_ref("v1.0", TAG, Some(COMMIT));
+        source.add_tag_ref("latest", COMMIT, None);
+        source
+    }
+
+    #[test]
+    fn test_parse_objects() {
+        let objects = format!("'commit {COMMIT} 200 150'\n'blob {BLOB} 1000 500'\n");
+        let records = parse_objects(&objects);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].kind, ObjectKind::Commit);
+        assert_eq!(records[1].hash.to_string(), BLOB);
+        assert_eq!(records[1].size, 1000);
+        assert_eq!(records[1].size_disk, 500);
+    }
+
+    #[test]
+    fn test_parse_objects_large_and_malformed_sizes() {
+        let objects = format!(
+            "'blob {BLOB} 6442450944 6442450000'\n'blob {OTHER_BLOB} -1 20'\n'blob {TAG} 20 abc'\n"
+        );
+        let records = parse_objects(&objects);
+
+        // sizes above 4 GiB are kept, bad sizes skip the object rather than panicking.
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].size, 6_442_450_944);
+        assert_eq!(records[0].size_disk, 6_442_450_000);
+    }
+
+    #[test]
+    fn test_parse_objects_delta_base() {
+        let zero = "0".repeat(40);
+        let objects = format!("'blob {BLOB} 1000 500 {zero}'\n'blob {OTHER_BLOB} 20 20 {BLOB}'\n");
+        let records = parse_objects(&objects);
+
+        assert_eq!(records[0].delta_base, None);
+        assert_eq!(records[1].delta_base, BLOB.parse().ok());
+    }
+
+    #[test]
+    fn test_delta_chain_depth() {
+        const THIRD_BLOB: &str = "7777777777777777777777777777777777777777";
+        let mut source = build_source();
+        source.add_object(ObjectKind::Blob, THIRD_BLOB, 30, 10);
+        source.set_delta_base(OTHER_BLOB, BLOB);
+        source.set_delta_base(THIRD_BLOB, OTHER_BLOB);
+
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+
+        let blob = container.blobs().get_by_hex(BLOB).unwrap().read().unwrap();
+        assert!(!blob.is_delta());
+
+        let other = container
+            .blobs()
+            .get_by_hex(OTHER_BLOB)
+            .unwrap()
+            .read()
+            .unwrap();
+        assert_eq!(other.delta_depth(), 1);
+        assert_eq!(other.delta_base(), Some(*blob.hash_index()));
+
+        let third = container
+            .blobs()
+            .get_by_hex(THIRD_BLOB)
+            .unwrap()
+            .read()
+            .unwrap();
+        assert_eq!(third.delta_depth(), 2);
+        assert_eq!(third.delta_base(), Some(*other.hash_index()));
+    }
+
+    #[test]
+    fn test_process_initial_repo() {
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&build_source(), &mut container).unwrap();
+
+        assert_eq!(container.commits().count(), 1);
+        assert_eq!(container.trees().count(), 2);
+        assert_eq!(container.blobs().count(), 2);
+        assert_eq!(container.tags().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_all_commit_deps() {
+        let source: Arc<dyn ObjectSource> = Arc::new(build_source());
+        let mut container = ObjectContainer::new();
+        process_initial_repo(source.as_ref(), &mut container).unwrap();
+        process_all_commit_deps(source.clone(), &container, &None)
+            .await
+            .unwrap();
+
+        let commit = container
+            .commits()
+            .get_by_hex(COMMIT)
+            .unwrap()
+            .read()
+            .unwrap();
+        assert_eq!(commit.tree_deps().len(), 2);
+        assert_eq!(commit.blob_deps().len(), 1);
+        assert_eq!(calc_commit_size(&commit, &container), 80 + 40 + 500);
+
+        let tree = container
+            .trees()
+            .get_by_hex(SUB_TREE)
+            .unwrap()
+            .read()
+            .unwrap();
+        assert_eq!(container.resolve_paths(tree.paths()), vec!["src"]);
+    }
+
+    #[test]
+    fn test_process_commit_deps_keeps_every_path() {
+        const SECOND_COMMIT: &str = "9999999999999999999999999999999999999999";
+        let mut source = build_source();
+        source.add_object(ObjectKind::Commit, SECOND_COMMIT, 200, 150);
+        source.add_commit_dep(SECOND_COMMIT, BLOB, "lib/main.rs");
+        source.add_commit_dep(SECOND_COMMIT, BLOB, "src/main.rs");
+
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        let commit_deps: HashMap<String, String> = [COMMIT, SECOND_COMMIT]
+            .iter()
+            .map(|hash| (hash.to_string(), source.commit_deps(hash).unwrap()))
+            .collect();
+        process_commit_deps(&commit_deps, &container);
+
+        let blob = container.blobs().get_by_hex(BLOB).unwrap().read().unwrap();
+        assert_eq!(
+            container.resolve_paths(blob.paths()),
+            vec!["lib/main.rs", "src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn test_process_commit_deps_sha256() {
+        let commit = "a1".repeat(32);
+        let tree = "b2".repeat(32);
+        let blob = "c3".repeat(32);
+        let mut source = InMemorySource::new();
+        source.add_object(ObjectKind::Commit, &commit, 200, 150);
+        source.add_object(ObjectKind::Tree, &tree, 100, 80);
+        source.add_object(ObjectKind::Blob, &blob, 1000, 500);
+        source.add_commit_dep(&commit, &tree, "");
+        source.add_commit_dep(&commit, &blob, "main.rs");
+
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        let commit_deps = HashMap::from([(commit.clone(), source.commit_deps(&commit).unwrap())]);
+        process_commit_deps(&commit_deps, &container);
+
+        let commit = container
+            .commits()
+            .get_by_hex(&commit)
+            .unwrap()
+            .read()
+            .unwrap();
+        assert_eq!(commit.tree_deps().len(), 1);
+        assert_eq!(commit.blob_deps().len(), 1);
+        assert_eq!(
+            container
+                .blobs()
+                .lookup_hash_for_index(&commit.blob_deps()[0]),
+            blob.parse().ok().as_ref()
+        );
+    }
+
+    #[test]
+    fn test_load_deps_round_trip_and_corrupt() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("deps.txt");
+        let source = build_source();
+        let header = DepsHeader {
+            tool_version: "0.1.0".to_string(),
+            repo_path: "/path/to/repo".to_string(),
+            object_format: ObjectFormat::Sha1,
+            refs_hash: 0x1234_abcd,
+        };
+        let commit_deps =
+            HashMap::from([(COMMIT.to_string(), source.commit_deps(COMMIT).unwrap())]);
+        save_deps(&header, &commit_deps, &path).unwrap();
+
+        let (loaded_header, loaded) = load_deps(&path, &header).unwrap().unwrap();
+        assert_eq!(loaded_header, header);
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded[COMMIT].contains(&format!("{BLOB} src/main.rs")));
+
+        let is_corrupt =
+            |path: &PathBuf| matches!(load_deps(path, &header), Err(Error::CacheCorrupt { .. }));
+        let saved = std::fs::read_to_string(&path).unwrap();
+
+        // truncated and edited.
+        std::fs::write(&path, &saved[..saved.len() - 20]).unwrap();
+        assert!(is_corrupt(&path));
+        std::fs::write(&path, saved.replace("src/main.rs", "src/lib.rs")).unwrap();
+        assert!(is_corrupt(&path));
+
+        // written before there was a header, in an older format, or for another repo. These
+        // are rebuilt rather than rejected.
+        let is_outdated = |path: &PathBuf| matches!(load_deps(path, &header), Ok(None));
+        std::fs::write(&path, format!(";\n{COMMIT}\n;\n")).unwrap();
+        assert!(is_outdated(&path));
+        std::fs::write(
+            &path,
+            saved.replace(
+                &format!("{DEPS_MAGIC} {DEPS_VERSION}"),
+                &format!("{DEPS_MAGIC} 2"),
+            ),
+        )
+        .unwrap();
+        assert!(is_outdated(&path));
+        let other_repo = DepsHeader {
+            repo_path: "/path/to/other".to_string(),
+            ..header.clone()
+        };
+        save_deps(&other_repo, &commit_deps, &path).unwrap();
+        assert!(is_outdated(&path));
+
+        // moved refs are not a reason to rebuild, the file is topped up instead.
+        let moved_refs = DepsHeader {
+            refs_hash: 0x5678_ef01,
+            ..header.clone()
+        };
+        save_deps(&moved_refs, &commit_deps, &path).unwrap();
+        assert!(matches!(load_deps(&path, &header), Ok(Some(_))));
+
+        // bad lines are caught even with a valid checksum.
+        let bad_dep = HashMap::from([(COMMIT.to_string(), "not a dep\n".to_string())]);
+        save_deps(&header, &bad_dep, &path).unwrap();
+        assert!(is_corrupt(&path));
+        let bad_hash = HashMap::from([("not a hash".to_string(), String::new())]);
+        save_deps(&header, &bad_hash, &path).unwrap();
+        assert!(is_corrupt(&path));
+    }
+
+    #[tokio::test]
+    async fn test_process_all_commit_deps_rebuilds_mismatched_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("deps.txt");
+        let source = build_source();
+
+        // A file from another version of the tool, with no deps for the commit.
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        let mut header = DepsHeader::for_source(&source, &container).unwrap();
+        let current = header.clone();
+        header.tool_version = "0.0.1".to_string();
+        save_deps(
+            &header,
+            &HashMap::from([(COMMIT.to_string(), String::new())]),
+            &path,
+        )
+        .unwrap();
+
+        process_all_commit_deps(Arc::new(source), &container, &Some(path.clone()))
+            .await
+            .unwrap();
+
+        let commit = container.commits().get_by_hex(COMMIT).unwrap();
+        assert_eq!(commit.read().unwrap().blob_deps().len(), 1);
+        let (_, saved) = load_deps(&path, &current).unwrap().unwrap();
+        assert!(!saved[COMMIT].is_empty());
+
+        // A file from before there was a header is rebuilt too.
+        std::fs::write(&path, format!(";\n{COMMIT}\n;\n")).unwrap();
+        let source = build_source();
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        process_all_commit_deps(Arc::new(source), &container, &Some(path.clone()))
+            .await
+            .unwrap();
+
+        let commit = container.commits().get_by_hex(COMMIT).unwrap();
+        assert_eq!(commit.read().unwrap().blob_deps().len(), 1);
+        let (_, saved) = load_deps(&path, &current).unwrap().unwrap();
+        assert!(!saved[COMMIT].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_all_commit_deps_incremental() {
+        const NEW_COMMIT: &str = "7777777777777777777777777777777777777777";
+        const NEW_TREE: &str = "8888888888888888888888888888888888888888";
+        let dir = tempfile::TempDir::new().unwrap();
+        let deps_path = Some(dir.path().join("deps.txt"));
+
+        let mut container = ObjectContainer::new();
+        let source = build_source();
+        process_initial_repo(&source, &mut container).unwrap();
+        process_all_commit_deps(Arc::new(source), &container, &deps_path)
+            .await
+            .unwrap();
+
+        // A new commit lands, only it should be read from the source.
+        let mut source = build_source();
+        source.add_object(ObjectKind::Commit, NEW_COMMIT, 210, 160);
+        source.add_object(ObjectKind::Tree, NEW_TREE, 100, 80);
+        source.add_commit_dep(NEW_COMMIT, NEW_TREE, "");
+        source.add_commit_dep(NEW_COMMIT, OTHER_BLOB, "src/lib.rs");
+        source.add_ref("refs/heads/main", NEW_COMMIT);
+        process_initial_repo(&source, &mut container).unwrap();
+        let header = DepsHeader::for_source(&source, &container).unwrap();
+        process_all_commit_deps(Arc::new(source), &container, &deps_path)
+            .await
+            .unwrap();
+
+        let blob = container.blobs().get_by_hex(BLOB).unwrap();
+        assert_eq!(blob.read().unwrap().commits().len(), 1);
+        let new_commit = container.commits().get_by_hex(NEW_COMMIT).unwrap();
+        assert_eq!(new_commit.read().unwrap().tree_deps().len(), 1);
+        assert_eq!(new_commit.read().unwrap().blob_deps().len(), 1);
+
+        // The new commit was added to the deps file, along with the refs it was saved for.
+        let (saved_header, loaded) = load_deps(deps_path.as_ref().unwrap(), &header)
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved_header.refs_hash, header.refs_hash);
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded[NEW_COMMIT].contains(&format!("{OTHER_BLOB} src/lib.rs")));
+
+        // A ref moving with no new commits only records the new refs.
+        let mut source = build_source();
+        source.add_ref("refs/heads/main", COMMIT);
+        let header = DepsHeader::for_source(&source, &container).unwrap();
+        process_all_commit_deps(Arc::new(source), &container, &deps_path)
+            .await
+            .unwrap();
+        let (saved_header, loaded) = load_deps(deps_path.as_ref().unwrap(), &header)
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved_header.refs_hash, header.refs_hash);
+        assert_eq!(loaded.len(), 2);
+    }
+
+    #[test]
+    fn test_process_commit_parents() {
+        const PARENT: &str = "8888888888888888888888888888888888888888";
+        let mut source = build_source();
+        source.add_object(ObjectKind::Commit, PARENT, 200, 150);
+        source.add_commit_parents(COMMIT, &[PARENT]);
+        source.add_commit_parents(PARENT, &[]);
+
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        process_commit_parents(&source, &container).unwrap();
+
+        let commit_index = *container.commits().get_index_by_hex(COMMIT).unwrap();
+        let parent_index = *container.commits().get_index_by_hex(PARENT).unwrap();
+        assert_eq!(container.ancestors(&commit_index), vec![parent_index]);
+        assert_eq!(container.descendants(&parent_index), vec![commit_index]);
+        assert_eq!(container.topo_order(), vec![parent_index, commit_index]);
+    }
+
+    #[test]
+    fn test_process_commit_metadata() {
+        let mut source = build_source();
+        source.add_commit_metadata(
+            COMMIT,
+            ("Jane Doe", "jane@example.com"),
+            1_700_000_000,
+            1_700_000_100,
+            "Add large assets",
+        );
+
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        process_commit_metadata(&source, &container).unwrap();
+
+        let commit = container
+            .commits()
+            .get_by_hex(COMMIT)
+            .unwrap()
+            .read()
+            .unwrap();
+        let metadata = commit.metadata().unwrap();
+        assert_eq!(metadata.author_time, 1_700_000_000);
+        assert_eq!(metadata.commit_time, 1_700_000_100);
+        assert_eq!(
+            container.resolve_string(metadata.author_name).as_deref(),
+            Some("Jane Doe")
+        );
+        assert_eq!(
+            container.resolve_string(metadata.author_email).as_deref(),
+            Some("jane@example.com")
+        );
+        assert_eq!(
+            container.resolve_string(metadata.subject).as_deref(),
+            Some("Add large assets")
+        );
+    }
+
+    #[test]
+    fn test_process_tags() {
+        let source = build_source();
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        process_tags(&source, &container).unwrap();
+
+        let commit = container
+            .commits()
+            .get_by_hex(COMMIT)
+            .unwrap()
+            .read()
+            .unwrap();
+        assert_eq!(commit.tag_deps(), &vec![0]);
+        assert_eq!(
+            commit.lightweight_tags(),
+            &vec!["refs/tags/latest".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_process_tags_outside_container() {
+        const OTHER_TAG: &str = "9999999999999999999999999999999999999999";
+        let mut source = build_source();
+        // An annotated tag whose tag object was not read, as happens with a scope.
+        source.add_tag_ref("v0.9", OTHER_TAG, Some(COMMIT));
+
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        process_tags(&source, &container).unwrap();
+
+        let commit = container.commits().get_by_hex(COMMIT).unwrap();
+        let commit = commit.read().unwrap();
+        assert_eq!(commit.tag_deps(), &vec![0]);
+        assert_eq!(
+            commit.lightweight_tags(),
+            &vec!["refs/tags/latest".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_process_refs() {
+        const OTHER_COMMIT: &str = "9999999999999999999999999999999999999999";
+        let mut source = build_source();
+        source.add_ref("HEAD", COMMIT);
+        source.add_ref("refs/heads/main", COMMIT);
+        source.add_ref("refs/heads/gone", OTHER_COMMIT);
+
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        process_tags(&source, &container).unwrap();
+        process_refs(&source, &container).unwrap();
+
+        // The annotated tag is followed to its commit and the ref outside the container is
+        // dropped.
+        let expected: Vec<(String, usize)> = [
+            "HEAD",
+            "refs/heads/main",
+            "refs/tags/latest",
+            "refs/tags/v1.0",
+        ]
+        .iter()
+        .map(|name| (name.to_string(), 0))
+        .collect();
+        assert_eq!(container.refs(), expected);
+    }
 }
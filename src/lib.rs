@@ -1,13 +1,26 @@
 pub mod blob;
 pub mod command_processing;
 pub mod commit;
+pub mod error;
+pub mod export_csv;
+pub mod export_sqlite;
 pub mod git_commands;
 pub mod git_processing;
 pub mod object_collection;
+pub mod object_id;
+pub mod object_source;
+pub mod odb_processing;
+pub mod pack;
 pub mod report_all;
 pub mod report_blobs;
+pub mod report_branches;
 pub mod report_commits;
+pub mod report_deltas;
+pub mod report_html;
+pub mod report_markdown;
+pub mod report_tags;
 pub mod report_trees;
+pub mod snapshot;
 pub mod tag;
 pub mod tree;
 pub mod utils;
@@ -1,16 +1,30 @@
 #![warn(clippy::all, clippy::pedantic)]
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use git_dag_analyzer::{
-    git_processing::{process_all_commit_deps, process_initial_repo, process_tags},
+    error::Error,
+    export_csv::export_csv,
+    export_sqlite::export_sqlite,
+    git_commands::GitCliSource,
+    git_processing::{
+        process_all_commit_deps, process_commit_metadata, process_commit_parents,
+        process_initial_repo, process_refs, process_tags,
+    },
     object_collection::ObjectContainer,
-    report_all::report_all,
-    report_blobs::report_blobs,
-    report_commits::report_commits,
-    report_trees::report_trees,
+    object_source::{ObjectSource, Scope},
+    odb_processing::Libgit2Source,
+    pack::PackSource,
+    report_all::{build_report, print_report, report_json, Report, ReportKind},
+    report_html::report_html,
+    report_markdown::report_markdown,
+    snapshot::{load_snapshot, save_snapshot, SnapshotHeader},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
-use std::path::PathBuf;
 use tokio::main;
 
 #[derive(Parser)]
@@ -20,10 +34,55 @@ struct Cli {
     #[arg(short, long, value_name = "REPO_PATH", required(true))]
     repo: Option<PathBuf>,
 
+    /// How the initial list of objects is read from the repo
+    #[arg(long, value_enum, default_value_t = Backend::Git)]
+    backend: Backend,
+
+    /// Only analyze what is reachable from these branches or tags, glob patterns such as
+    /// `release/*` are allowed. Can be repeated or comma separated. Defaults to every ref.
+    #[arg(long, value_name = "REF", value_delimiter = ',')]
+    refs: Vec<String>,
+
+    /// Only analyze the commits in a revision range such as `v1.0..v2.0`, along with the
+    /// objects they reference. Can be repeated.
+    #[arg(long, value_name = "RANGE")]
+    range: Vec<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Pipe `git rev-list` into `git cat-file`
+    Git,
+    /// Walk the reachable objects in process with libgit2. libgit2 can not tell how an object
+    /// is stored, so sizes on disk are the inflated size and no delta information is read
+    Libgit2,
+    /// Read sizes straight from the pack and loose object files
+    Pack,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Human readable text
+    Text,
+    /// A single JSON document, see the README for its fields
+    Json,
+    /// A self contained HTML page with sortable tables and size charts
+    Html,
+    /// Markdown short enough to post as a review comment
+    Markdown,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// A directory of CSV files, one per table
+    Csv,
+    /// A single sqlite database
+    Sqlite,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Outputs a report of repo size information
@@ -45,6 +104,32 @@ enum Commands {
 
         #[arg(short, long)]
         blobs: bool,
+
+        /// How objects are stored, whole or as deltas, and the deepest delta chains
+        #[arg(short, long)]
+        deltas: bool,
+
+        /// Annotated and lightweight tags, and the largest tag objects
+        #[arg(long)]
+        tags: bool,
+
+        /// How much each branch and tag keeps alive that the default branch does not, and
+        /// how much deleting it would save
+        #[arg(long)]
+        branches: bool,
+
+        /// Load everything from a snapshot written by the `snapshot` command rather than
+        /// reading the repo.
+        #[arg(long, value_name = "SNAPSHOT", conflicts_with = "save_deps")]
+        snapshot: Option<PathBuf>,
+
+        /// How the reports are written to stdout. Progress is always written to stderr.
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        /// Write the JSON, HTML or Markdown report to this file rather than stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
     },
     /// Only process the data
     ProcessOnly {
@@ -64,6 +149,139 @@ enum Commands {
         #[arg(short, long)]
         labels: bool,
     },
+    /// Process everything and save the linked objects to a snapshot, which can be loaded
+    /// with `reports --snapshot` in a fraction of the time
+    Snapshot {
+        /// Where to write the snapshot. If it already exists it is updated with the commits
+        /// made since it was taken
+        #[arg(value_name = "SNAPSHOT")]
+        output: PathBuf,
+
+        /// If set and the file is not present, it will be created for further use. If
+        /// present then it will be loaded for processeing. Saving the time it normally
+        /// takes to process commit deps.
+        #[arg(short, long, value_name = "SAVE_LOCATION")]
+        save_deps: Option<PathBuf>,
+    },
+    /// Process everything and write every object, with its sizes, paths and commits, to
+    /// tables for loading into a spreadsheet, pandas or sqlite
+    Export {
+        /// The directory to write the CSV files to, created if it does not exist. Or with
+        /// `--format sqlite` the database file, which is replaced if it exists
+        #[arg(value_name = "OUTPUT")]
+        output: PathBuf,
+
+        /// What to write the tables as
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// If set and the file is not present, it will be created for further use. If
+        /// present then it will be loaded for processeing. Saving the time it normally
+        /// takes to process commit deps.
+        #[arg(short, long, value_name = "SAVE_LOCATION", conflicts_with = "snapshot")]
+        save_deps: Option<PathBuf>,
+
+        /// Load everything from a snapshot written by the `snapshot` command rather than
+        /// reading the repo.
+        #[arg(long, value_name = "SNAPSHOT")]
+        snapshot: Option<PathBuf>,
+    },
+}
+
+fn object_source(backend: Backend, repo_path: &Path, scope: Scope) -> Arc<dyn ObjectSource> {
+    match backend {
+        Backend::Git => Arc::new(GitCliSource::new(repo_path).with_scope(scope)),
+        Backend::Libgit2 => Arc::new(Libgit2Source::new(repo_path).with_scope(scope)),
+        Backend::Pack => Arc::new(PackSource::new(repo_path).with_scope(scope)),
+    }
+}
+
+// Read everything from the repo into `container`: objects, commit deps, parents, metadata,
+// tags and refs. Anything already in the container, as when updating a snapshot, is kept.
+async fn process_everything(
+    source: &Arc<dyn ObjectSource>,
+    container: &mut ObjectContainer,
+    save_deps: Option<&PathBuf>,
+) -> Result<()> {
+    process_initial_repo(source.as_ref(), container)?;
+    process_all_commit_deps(source.clone(), container, &save_deps.cloned()).await?;
+    process_commit_parents(source.as_ref(), container)?;
+    process_commit_metadata(source.as_ref(), container)?;
+    // annotated tags have to be linked to their commits before the refs.
+    process_tags(source.as_ref(), container)?;
+    process_refs(source.as_ref(), container)?;
+    Ok(())
+}
+
+// Read only what the `kinds` of report picked use. The commit deps are needed by every
+// report, the parents only by the branch report and the metadata only by the commit report.
+async fn process_for_reports(
+    source: &Arc<dyn ObjectSource>,
+    container: &mut ObjectContainer,
+    kinds: &[ReportKind],
+    save_deps: Option<&PathBuf>,
+) -> Result<()> {
+    process_initial_repo(source.as_ref(), container)?;
+    process_all_commit_deps(source.clone(), container, &save_deps.cloned()).await?;
+    if kinds.contains(&ReportKind::Branches) {
+        process_commit_parents(source.as_ref(), container)?;
+    }
+    if kinds.contains(&ReportKind::Commits) {
+        process_commit_metadata(source.as_ref(), container)?;
+    }
+    if kinds.contains(&ReportKind::Tags) || kinds.contains(&ReportKind::Branches) {
+        // annotated tags have to be linked to their commits before the refs.
+        process_tags(source.as_ref(), container)?;
+        process_refs(source.as_ref(), container)?;
+    }
+    Ok(())
+}
+
+// Load a snapshot, which must have been taken of the repo and scope in `header`.
+fn open_snapshot(path: &Path, header: &SnapshotHeader) -> Result<ObjectContainer> {
+    let container = load_snapshot(path, header)?;
+    eprintln!("Loaded snapshot {}", path.display());
+    Ok(container)
+}
+
+// The snapshot at `path` to update, or an empty container when there is none yet or it was
+// taken of another repo or scope.
+fn existing_snapshot(path: &Path, header: &SnapshotHeader) -> Result<ObjectContainer> {
+    if !path.exists() {
+        return Ok(ObjectContainer::new());
+    }
+    match load_snapshot(path, header) {
+        Ok(container) => {
+            eprintln!("Updating snapshot {}", path.display());
+            Ok(container)
+        }
+        Err(Error::SnapshotMismatch { reason, .. }) => {
+            eprintln!("Snapshot was {reason}, taking a new one");
+            Ok(ObjectContainer::new())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Text is printed as each report is, the other formats go to `output` if given.
+fn write_report(report: &Report, format: Format, output: Option<&PathBuf>) -> Result<()> {
+    let document = match format {
+        Format::Text => {
+            print_report(report);
+            return Ok(());
+        }
+        Format::Json => report_json(report),
+        Format::Html => report_html(report),
+        Format::Markdown => report_markdown(report),
+    };
+    match output {
+        Some(output) => {
+            fs::write(output, document)?;
+            eprintln!("Wrote report to {}", output.display());
+        }
+        None => println!("{document}"),
+    }
+    Ok(())
 }
 
 #[main]
@@ -72,6 +290,9 @@ async fn main() -> Result<()> {
 
     // since this is required by the cli, we can safely unwrap here.
     let repo_path = cli.repo.as_deref().unwrap();
+    let scope = Scope::new(&cli.refs, &cli.range)?;
+    let source = object_source(cli.backend, repo_path, scope.clone());
+    let snapshot_header = || SnapshotHeader::new(repo_path, &scope);
     let mut container = ObjectContainer::new();
 
     match &cli.command {
@@ -81,24 +302,41 @@ async fn main() -> Result<()> {
             save_deps,
             trees,
             blobs,
+            deltas,
+            tags,
+            branches,
+            snapshot,
+            format,
+            output,
         }) => {
-            // first we have to process everything
-            process_initial_repo(repo_path, &mut container);
+            if output.is_some() && matches!(format, Format::Text) {
+                bail!("--output needs --format json, html or markdown");
+            }
+            // only the reports picked are built, none if nothing was picked.
+            let mut kinds: Vec<ReportKind> = [
+                (*commits, ReportKind::Commits),
+                (*trees, ReportKind::Trees),
+                (*blobs, ReportKind::Blobs),
+                (*tags, ReportKind::Tags),
+                (*deltas, ReportKind::Deltas),
+                (*branches, ReportKind::Branches),
+            ]
+            .into_iter()
+            .filter_map(|(wanted, kind)| wanted.then_some(kind))
+            .collect();
+            if *all {
+                kinds = ReportKind::ALL.to_vec();
+            }
 
-            // required for all three reporting types.
-            process_all_commit_deps(repo_path, &container, save_deps).await?;
+            if let Some(snapshot) = snapshot {
+                // a snapshot already holds everything, tags included.
+                container = open_snapshot(snapshot, &snapshot_header()?)?;
+            } else {
+                process_for_reports(&source, &mut container, &kinds, save_deps.as_ref()).await?;
+            }
 
             // Do reports
-            if *all {
-                process_tags(repo_path, &container);
-                report_all(&container);
-            } else if *commits {
-                report_commits(&container);
-            } else if *trees {
-                report_trees(&container);
-            } else if *blobs {
-                report_blobs(&container);
-            }
+            write_report(&build_report(&container, &kinds), *format, output.as_ref())?;
         }
         Some(Commands::ProcessOnly {
             all,
@@ -106,15 +344,45 @@ async fn main() -> Result<()> {
             save_deps,
             labels,
         }) => {
-            process_initial_repo(repo_path, &mut container);
             if *all {
-                process_all_commit_deps(repo_path, &container, save_deps).await?;
-                process_tags(repo_path, &container);
-            } else if *commits {
-                process_all_commit_deps(repo_path, &container, save_deps).await?;
+                process_everything(&source, &mut container, save_deps.as_ref()).await?;
+                return Ok(());
+            }
+
+            process_initial_repo(source.as_ref(), &mut container)?;
+            if *commits {
+                process_all_commit_deps(source.clone(), &container, save_deps).await?;
+                process_commit_parents(source.as_ref(), &container)?;
+                process_commit_metadata(source.as_ref(), &container)?;
             } else if *labels {
-                process_tags(repo_path, &container);
+                process_tags(source.as_ref(), &container)?;
+            }
+        }
+        Some(Commands::Snapshot { output, save_deps }) => {
+            // An existing snapshot is updated, only what was added to the repo since it was
+            // taken needs to be read. One taken of another repo or scope is replaced.
+            let header = snapshot_header()?;
+            container = existing_snapshot(output, &header)?;
+            process_everything(&source, &mut container, save_deps.as_ref()).await?;
+            save_snapshot(&container, output, &header)?;
+            eprintln!("Snapshot saved to {}", output.display());
+        }
+        Some(Commands::Export {
+            output,
+            format,
+            save_deps,
+            snapshot,
+        }) => {
+            if let Some(snapshot) = snapshot {
+                container = open_snapshot(snapshot, &snapshot_header()?)?;
+            } else {
+                process_everything(&source, &mut container, save_deps.as_ref()).await?;
+            }
+            match format {
+                ExportFormat::Csv => export_csv(&container, output)?,
+                ExportFormat::Sqlite => export_sqlite(&container, output)?,
             }
+            eprintln!("Exported to {}", output.display());
         }
         None => {}
     }
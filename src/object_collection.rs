@@ -1,18 +1,56 @@
 use crate::blob::*;
 use crate::commit::*;
+use crate::object_id::ObjectId;
 use crate::tag::*;
 use crate::tree::*;
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
 
 pub trait Properties {
     fn hash_index(&self) -> &usize;
     fn set_index(&mut self, index: usize);
 }
 
+/// How an object is stored in its pack. Objects stored whole have a depth of zero, deltas
+/// have the number of deltas that must be applied to rebuild them. The base index is into
+/// the container of the same object type, and is None when the base is not in the container.
+pub trait Delta {
+    fn delta_base(&self) -> Option<usize>;
+    fn delta_depth(&self) -> u32;
+    fn set_delta(&mut self, base_index: Option<usize>, depth: u32);
+
+    fn is_delta(&self) -> bool {
+        self.delta_depth() > 0
+    }
+}
+
+// Every object type stores its delta information the same way, in `delta_base` and
+// `delta_depth` fields, so they all share this implementation.
+macro_rules! impl_delta {
+    ($object:ty) => {
+        impl $crate::object_collection::Delta for $object {
+            fn delta_base(&self) -> Option<usize> {
+                self.delta_base
+            }
+
+            fn delta_depth(&self) -> u32 {
+                self.delta_depth
+            }
+
+            fn set_delta(&mut self, base_index: Option<usize>, depth: u32) {
+                self.delta_base = base_index;
+                self.delta_depth = depth;
+            }
+        }
+    };
+}
+pub(crate) use impl_delta;
+
 pub struct BasicObjectContainer<T> {
     items: Vec<RwLock<T>>,
-    lookup: HashMap<String, usize>,
+    lookup: HashMap<ObjectId, usize>,
+    // The id of each item, by index, so ids can be found without searching the lookup.
+    ids: Vec<ObjectId>,
 }
 
 impl<T> BasicObjectContainer<T>
@@ -23,21 +61,33 @@ where
         BasicObjectContainer {
             items: Vec::new(),
             lookup: HashMap::new(),
+            ids: Vec::new(),
         }
     }
 
-    pub fn add(&mut self, hash: &str, object: T) {
+    pub fn add(&mut self, id: ObjectId, object: T) {
         let index = *object.hash_index();
         self.items.push(RwLock::new(object));
-        self.lookup.insert(hash.to_owned(), index);
+        self.ids.push(id);
+        self.lookup.insert(id, index);
+    }
+
+    pub fn get_index(&self, id: &ObjectId) -> Option<&usize> {
+        self.lookup.get(id)
     }
 
-    pub fn get_index(&self, hash: &str) -> Option<&usize> {
-        self.lookup.get(hash)
+    pub fn get(&self, id: &ObjectId) -> Option<&RwLock<T>> {
+        self.lookup.get(id).map(|&index| &self.items[index])
     }
 
-    pub fn get(&self, hash: &str) -> Option<&RwLock<T>> {
-        self.lookup.get(hash).map(|&index| &self.items[index])
+    /// Look up an object by the hex form of its id. None if the hex is not a valid id.
+    pub fn get_by_hex(&self, hex: &str) -> Option<&RwLock<T>> {
+        ObjectId::from_hex(hex).and_then(|id| self.get(&id))
+    }
+
+    /// Look up the index of an object by the hex form of its id.
+    pub fn get_index_by_hex(&self, hex: &str) -> Option<&usize> {
+        ObjectId::from_hex(hex).and_then(|id| self.get_index(&id))
     }
 
     pub fn get_by_index(&self, index: &usize) -> &RwLock<T> {
@@ -48,7 +98,7 @@ where
         self.items.len()
     }
 
-    pub fn object_hash_iter(&self) -> impl Iterator<Item = (&String, &usize)> {
+    pub fn object_hash_iter(&self) -> impl Iterator<Item = (&ObjectId, &usize)> {
         self.lookup.iter()
     }
 
@@ -56,13 +106,8 @@ where
         self.items.iter()
     }
 
-    // Note that this is slow and should not be done in a loop.
-    pub fn lookup_hash_for_index(&self, index: &usize) -> Option<&String> {
-        let hash = self
-            .lookup
-            .iter()
-            .find_map(|(key, &val)| if val == *index { Some(key) } else { None });
-        hash
+    pub fn lookup_hash_for_index(&self, index: &usize) -> Option<&ObjectId> {
+        self.ids.get(*index)
     }
 }
 
@@ -75,11 +120,154 @@ where
     }
 }
 
+/// Stores each distinct string once and hands out a small id for it. Used for values that
+/// repeat across many objects, such as commit authors.
+#[derive(Default)]
+pub struct StringInterner {
+    strings: Vec<Arc<str>>,
+    lookup: HashMap<Arc<str>, u32>,
+}
+
+impl StringInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, value: &str) -> u32 {
+        if let Some(&id) = self.lookup.get(value) {
+            return id;
+        }
+
+        let id = self.strings.len() as u32;
+        let value: Arc<str> = Arc::from(value);
+        self.strings.push(value.clone());
+        self.lookup.insert(value, id);
+        id
+    }
+
+    pub fn resolve(&self, id: u32) -> Option<&Arc<str>> {
+        self.strings.get(id as usize)
+    }
+
+    pub fn count(&self) -> usize {
+        self.strings.len()
+    }
+}
+
+/// Interns paths as a tree of path components, so the directories shared by many paths are
+/// only stored once. Each path is identified by the id of its last component, and the id of
+/// the empty path ( the root ) is always `PathTable::ROOT`.
+pub struct PathTable {
+    // (parent id, name id) for every path, the root is its own parent.
+    nodes: Vec<(u32, u32)>,
+    children: Vec<Vec<u32>>,
+    names: StringInterner,
+    lookup: HashMap<(u32, u32), u32>,
+}
+
+impl PathTable {
+    pub const ROOT: u32 = 0;
+
+    pub fn new() -> Self {
+        let mut names = StringInterner::new();
+        let root_name = names.intern("");
+        PathTable {
+            nodes: vec![(Self::ROOT, root_name)],
+            children: vec![Vec::new()],
+            names,
+            lookup: HashMap::new(),
+        }
+    }
+
+    /// Add a `/` separated path, returning its id. Adding the same path again returns the same
+    /// id.
+    pub fn intern(&mut self, path: &str) -> u32 {
+        let mut id = Self::ROOT;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            id = self.intern_child(id, component);
+        }
+        id
+    }
+
+    /// Add a single component `name` inside the directory `parent`, returning its id.
+    pub fn intern_child(&mut self, parent: u32, name: &str) -> u32 {
+        let name = self.names.intern(name);
+        if let Some(&child) = self.lookup.get(&(parent, name)) {
+            return child;
+        }
+
+        let child = self.nodes.len() as u32;
+        self.nodes.push((parent, name));
+        self.children.push(Vec::new());
+        self.children[parent as usize].push(child);
+        self.lookup.insert((parent, name), child);
+        child
+    }
+
+    /// The id of a path if it has already been added.
+    pub fn find(&self, path: &str) -> Option<u32> {
+        let mut id = Self::ROOT;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            let name = self.names.lookup.get(component)?;
+            id = *self.lookup.get(&(id, *name))?;
+        }
+        Some(id)
+    }
+
+    /// The full path for an id.
+    pub fn resolve(&self, id: u32) -> Option<String> {
+        let mut components: Vec<&str> = Vec::new();
+        let mut current = id;
+        while current != Self::ROOT {
+            let (parent, name) = *self.nodes.get(current as usize)?;
+            components.push(self.names.resolve(name)?);
+            current = parent;
+        }
+        components.reverse();
+        Some(components.join("/"))
+    }
+
+    /// The last component of a path, the file or directory name.
+    pub fn name(&self, id: u32) -> Option<&str> {
+        let (_, name) = self.nodes.get(id as usize)?;
+        self.names.resolve(*name).map(|name| &**name)
+    }
+
+    /// The directory containing a path, None for the root.
+    pub fn parent(&self, id: u32) -> Option<u32> {
+        if id == Self::ROOT {
+            return None;
+        }
+        self.nodes.get(id as usize).map(|(parent, _)| *parent)
+    }
+
+    /// The paths directly inside a directory.
+    pub fn children(&self, id: u32) -> &[u32] {
+        self.children
+            .get(id as usize)
+            .map_or(&[], |children| children.as_slice())
+    }
+
+    pub fn count(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+impl Default for PathTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct ObjectContainer {
     commits: BasicObjectContainer<Commit>,
     blobs: BasicObjectContainer<Blob>,
     trees: BasicObjectContainer<Tree>,
     tags: BasicObjectContainer<Tag>,
+    strings: RwLock<StringInterner>,
+    paths: RwLock<PathTable>,
+    // (name, commit index) of every ref that points at a commit in the container.
+    refs: RwLock<Vec<(String, usize)>>,
 }
 
 impl ObjectContainer {
@@ -89,7 +277,76 @@ impl ObjectContainer {
             blobs: BasicObjectContainer::new(),
             trees: BasicObjectContainer::new(),
             tags: BasicObjectContainer::new(),
+            strings: RwLock::new(StringInterner::new()),
+            paths: RwLock::new(PathTable::new()),
+            refs: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Intern a path shared by blobs and trees, returning its id.
+    pub fn intern_path(&self, path: &str) -> u32 {
+        // Most paths are seen many times, so only take the write lock for new ones.
+        if let Some(id) = self.paths.read().unwrap().find(path) {
+            return id;
         }
+        self.paths.write().unwrap().intern(path)
+    }
+
+    /// The full path for an id returned by `intern_path`.
+    pub fn resolve_path(&self, id: u32) -> Option<String> {
+        self.paths.read().unwrap().resolve(id)
+    }
+
+    /// The full paths for a set of ids, such as those of a blob or tree, sorted by path so
+    /// the order is the same from run to run.
+    pub fn resolve_paths(&self, ids: &[u32]) -> Vec<String> {
+        let paths = self.paths.read().unwrap();
+        let mut resolved: Vec<String> = ids
+            .iter()
+            .map(|id| paths.resolve(*id).unwrap_or_default())
+            .collect();
+        resolved.sort();
+        resolved
+    }
+
+    /// The id of a path, if any object has been seen at it.
+    pub fn find_path(&self, path: &str) -> Option<u32> {
+        self.paths.read().unwrap().find(path)
+    }
+
+    /// The ids of the paths directly inside the directory `id`.
+    pub fn path_children(&self, id: u32) -> Vec<u32> {
+        self.paths.read().unwrap().children(id).to_vec()
+    }
+
+    pub fn paths(&self) -> &RwLock<PathTable> {
+        &self.paths
+    }
+
+    pub fn strings(&self) -> &RwLock<StringInterner> {
+        &self.strings
+    }
+
+    /// Intern a string shared across objects, returning its id.
+    pub fn intern(&self, value: &str) -> u32 {
+        self.strings.write().unwrap().intern(value)
+    }
+
+    /// Look up a string interned with `intern`.
+    pub fn resolve_string(&self, id: u32) -> Option<Arc<str>> {
+        self.strings.read().unwrap().resolve(id).cloned()
+    }
+
+    /// Replace the refs with `refs`, the name of each ref (`HEAD` or a full name such as
+    /// `refs/heads/main`) and the index of the commit it points at.
+    pub fn set_refs(&self, mut refs: Vec<(String, usize)>) {
+        refs.sort();
+        *self.refs.write().unwrap() = refs;
+    }
+
+    /// Every ref set with `set_refs`, sorted by name.
+    pub fn refs(&self) -> Vec<(String, usize)> {
+        self.refs.read().unwrap().clone()
     }
 
     pub fn mut_commits(&mut self) -> &mut BasicObjectContainer<Commit> {
@@ -123,6 +380,104 @@ impl ObjectContainer {
     pub fn tags(&self) -> &BasicObjectContainer<Tag> {
         &self.tags
     }
+
+    /// All commits reachable by following parents from `commit_index`, not including the
+    /// commit itself. Nearest commits come first.
+    pub fn ancestors(&self, commit_index: &usize) -> Vec<usize> {
+        self.walk_commits(commit_index, |commit| commit.parents().clone())
+    }
+
+    /// All commits that have `commit_index` as an ancestor, not including the commit itself.
+    /// Nearest commits come first.
+    pub fn descendants(&self, commit_index: &usize) -> Vec<usize> {
+        self.walk_commits(commit_index, |commit| commit.children().clone())
+    }
+
+    /// The best common ancestors of two commits, as with `git merge-base --all`. A common
+    /// ancestor is best if it is not an ancestor of any other common ancestor. A commit counts
+    /// as its own ancestor here, so the merge base of a commit and its descendant is the
+    /// commit.
+    pub fn merge_bases(&self, first: &usize, second: &usize) -> Vec<usize> {
+        let mut first_ancestors: HashSet<usize> = self.ancestors(first).into_iter().collect();
+        first_ancestors.insert(*first);
+
+        let mut common: Vec<usize> = Vec::new();
+        let mut second_ancestors = self.ancestors(second);
+        second_ancestors.insert(0, *second);
+        for index in second_ancestors {
+            if first_ancestors.contains(&index) {
+                common.push(index);
+            }
+        }
+
+        // Drop any common ancestor that is reachable from another one.
+        let mut redundant: HashSet<usize> = HashSet::new();
+        for index in &common {
+            if !redundant.contains(index) {
+                redundant.extend(self.ancestors(index));
+            }
+        }
+        common.retain(|index| !redundant.contains(index));
+        common.sort();
+        common
+    }
+
+    /// A single best common ancestor of two commits, if they have one.
+    pub fn merge_base(&self, first: &usize, second: &usize) -> Option<usize> {
+        self.merge_bases(first, second).into_iter().next()
+    }
+
+    /// Every commit ordered so that each commit comes after all of its parents.
+    pub fn topo_order(&self) -> Vec<usize> {
+        let count = self.commits.count();
+        let mut waiting: Vec<usize> = Vec::with_capacity(count);
+        let mut ready: VecDeque<usize> = VecDeque::new();
+        for index in 0..count {
+            let parents = self
+                .commits
+                .get_by_index(&index)
+                .read()
+                .unwrap()
+                .parents()
+                .len();
+            waiting.push(parents);
+            if parents == 0 {
+                ready.push_back(index);
+            }
+        }
+
+        let mut order = Vec::with_capacity(count);
+        while let Some(index) = ready.pop_front() {
+            order.push(index);
+            let commit = self.commits.get_by_index(&index).read().unwrap();
+            for child in commit.children() {
+                waiting[*child] -= 1;
+                if waiting[*child] == 0 {
+                    ready.push_back(*child);
+                }
+            }
+        }
+        order
+    }
+
+    // Breadth first walk of the commit graph from `start`, following the edges returned by
+    // `next`.
+    fn walk_commits(&self, start: &usize, next: impl Fn(&Commit) -> Vec<usize>) -> Vec<usize> {
+        let mut seen: HashSet<usize> = HashSet::from([*start]);
+        let mut queue: VecDeque<usize> = VecDeque::from([*start]);
+        let mut found = Vec::new();
+
+        while let Some(index) = queue.pop_front() {
+            let edges = next(&self.commits.get_by_index(&index).read().unwrap());
+            for edge in edges {
+                if seen.insert(edge) {
+                    found.push(edge);
+                    queue.push_back(edge);
+                }
+            }
+        }
+        found
+    }
 }
 
 impl Default for ObjectContainer {
@@ -135,6 +490,8 @@ impl Default for ObjectContainer {
 mod tests {
     use super::*;
 
+    const MOCK_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
     #[derive(Debug)]
     struct MockObject {
         index: usize,
@@ -153,20 +510,20 @@ mod tests {
     #[test]
     fn test_basic_object_container_add_and_get() {
         let mut container = BasicObjectContainer::new();
-        let hash = "mock_hash";
+        let hash = ObjectId::from_hex(MOCK_HASH).unwrap();
         let object = MockObject { index: 0 };
 
         container.add(hash, object);
 
         assert_eq!(container.count(), 1);
-        assert!(container.get_index(hash).is_some());
-        assert_eq!(container.get_index(hash).unwrap(), &0);
+        assert!(container.get_index(&hash).is_some());
+        assert_eq!(container.get_index(&hash).unwrap(), &0);
     }
 
     #[test]
     fn test_basic_object_container_get_by_index() {
         let mut container = BasicObjectContainer::new();
-        let hash = "mock_hash";
+        let hash = ObjectId::from_hex(MOCK_HASH).unwrap();
         let object = MockObject { index: 0 };
 
         container.add(hash, object);
@@ -178,50 +535,175 @@ mod tests {
     #[test]
     fn test_basic_object_container_lookup_hash_for_index() {
         let mut container = BasicObjectContainer::new();
-        let hash = "mock_hash";
+        let hash = ObjectId::from_hex(MOCK_HASH).unwrap();
         let object = MockObject { index: 0 };
 
         container.add(hash, object);
 
         let retrieved_hash = container.lookup_hash_for_index(&0);
         assert!(retrieved_hash.is_some());
-        assert_eq!(retrieved_hash.unwrap(), hash);
+        assert_eq!(retrieved_hash.unwrap(), &hash);
+        assert!(container.lookup_hash_for_index(&1).is_none());
+    }
+
+    #[test]
+    fn test_string_interner() {
+        let mut interner = StringInterner::new();
+        let first = interner.intern("Jane Doe");
+        let second = interner.intern("John Doe");
+
+        assert_eq!(interner.intern("Jane Doe"), first);
+        assert_ne!(first, second);
+        assert_eq!(interner.count(), 2);
+        assert_eq!(interner.resolve(second).map(|s| &**s), Some("John Doe"));
+        assert!(interner.resolve(5).is_none());
+    }
+
+    #[test]
+    fn test_path_table() {
+        let mut paths = PathTable::new();
+        let main = paths.intern("src/main.rs");
+        let lib = paths.intern("src/lib.rs");
+        let readme = paths.intern("README.md");
+
+        assert_eq!(paths.intern("src/main.rs"), main);
+        assert_eq!(paths.intern(""), PathTable::ROOT);
+        assert_eq!(paths.resolve(main).as_deref(), Some("src/main.rs"));
+        assert_eq!(paths.resolve(PathTable::ROOT).as_deref(), Some(""));
+        assert_eq!(paths.name(lib), Some("lib.rs"));
+
+        // root, src, main.rs, lib.rs and README.md, src is only stored once.
+        assert_eq!(paths.count(), 5);
+
+        let src = paths.find("src").unwrap();
+        assert_eq!(paths.parent(main), Some(src));
+        assert_eq!(paths.children(src), &[main, lib]);
+        assert_eq!(paths.children(PathTable::ROOT), &[src, readme]);
+        assert!(paths.children(main).is_empty());
+        assert!(paths.find("src/other.rs").is_none());
     }
 
     #[test]
     fn test_object_container_add_commit() {
         let mut container = ObjectContainer::new();
-        let hash = "mock_commit_hash";
+        let hash = ObjectId::from_hex(MOCK_HASH).unwrap();
         let commit = Commit::new(0, 123, 456);
 
         container.mut_commits().add(hash, commit);
 
         assert_eq!(container.commits().count(), 1);
-        assert!(container.commits().get_index(hash).is_some());
+        assert!(container.commits().get_index(&hash).is_some());
     }
 
     #[test]
     fn test_object_container_retrieve_commit() {
         let mut container = ObjectContainer::new();
-        let hash = "mock_commit_hash";
+        let hash = ObjectId::from_hex(MOCK_HASH).unwrap();
         let commit = Commit::new(0, 123, 456);
 
         container.mut_commits().add(hash, commit);
 
-        let retrieved_commit = container.commits().get(hash).read().unwrap();
+        let retrieved_commit = container
+            .commits()
+            .get_by_hex(MOCK_HASH)
+            .unwrap()
+            .read()
+            .unwrap();
         assert_eq!(retrieved_commit.hash_index(), &0);
     }
 
+    fn object_id(index: usize) -> ObjectId {
+        ObjectId::from_bytes(&[index as u8; ObjectId::SHA1_LEN]).unwrap()
+    }
+
+    // Builds the graph
+    //   0 - 1 - 3 - 4
+    //     \- 2 -/
+    fn build_commit_graph() -> ObjectContainer {
+        let mut container = ObjectContainer::new();
+        for index in 0..5 {
+            container
+                .mut_commits()
+                .add(object_id(index), Commit::new(index, 0, 0));
+        }
+
+        for (child, parents) in [(1, vec![0]), (2, vec![0]), (3, vec![1, 2]), (4, vec![3])] {
+            for parent in parents {
+                container
+                    .commits()
+                    .get_by_index(&child)
+                    .write()
+                    .unwrap()
+                    .add_parent(&parent);
+                container
+                    .commits()
+                    .get_by_index(&parent)
+                    .write()
+                    .unwrap()
+                    .add_child(&child);
+            }
+        }
+        container
+    }
+
+    #[test]
+    fn test_object_container_ancestors_and_descendants() {
+        let container = build_commit_graph();
+
+        let mut ancestors = container.ancestors(&3);
+        ancestors.sort();
+        assert_eq!(ancestors, vec![0, 1, 2]);
+
+        let mut descendants = container.descendants(&1);
+        descendants.sort();
+        assert_eq!(descendants, vec![3, 4]);
+        assert!(container.descendants(&4).is_empty());
+    }
+
+    #[test]
+    fn test_object_container_merge_base() {
+        let container = build_commit_graph();
+
+        assert_eq!(container.merge_bases(&1, &2), vec![0]);
+        assert_eq!(container.merge_base(&4, &2), Some(2));
+        assert_eq!(container.merge_base(&3, &3), Some(3));
+    }
+
+    #[test]
+    fn test_object_container_lookup_hash_for_every_index() {
+        let container = build_commit_graph();
+        for index in 0..5 {
+            assert_eq!(
+                container.commits().lookup_hash_for_index(&index),
+                Some(&object_id(index))
+            );
+        }
+    }
+
+    #[test]
+    fn test_object_container_topo_order() {
+        let container = build_commit_graph();
+        let order = container.topo_order();
+
+        assert_eq!(order.len(), 5);
+        let position = |index: usize| order.iter().position(|&i| i == index).unwrap();
+        assert!(position(0) < position(1));
+        assert!(position(0) < position(2));
+        assert!(position(1) < position(3));
+        assert!(position(2) < position(3));
+        assert!(position(3) < position(4));
+    }
+
     #[test]
     fn test_object_container_lookup_commit_hash() {
         let mut container = ObjectContainer::new();
-        let hash = "mock_commit_hash";
+        let hash = ObjectId::from_hex(MOCK_HASH).unwrap();
         let commit = Commit::new(0, 123, 456);
 
         container.mut_commits().add(hash, commit);
 
         let retrieved_hash = container.commits().lookup_hash_for_index(&0);
         assert!(retrieved_hash.is_some());
-        assert_eq!(retrieved_hash.unwrap(), hash);
+        assert_eq!(retrieved_hash.unwrap(), &hash);
     }
 }
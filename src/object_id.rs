@@ -0,0 +1,227 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The binary form of a git object id. Ids are kept as raw bytes rather than hex strings to
+/// halve their size and make hashing cheaper, which matters with tens of millions of
+/// objects. There is room for both sha1 ids (20 bytes) and sha256 ids (32 bytes), unused
+/// bytes are always zero.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ObjectId {
+    bytes: [u8; ObjectId::MAX_LEN],
+    len: u8,
+}
+
+impl ObjectId {
+    /// The length in bytes of a sha1 object id.
+    pub const SHA1_LEN: usize = 20;
+    /// The length in bytes of a sha256 object id.
+    pub const SHA256_LEN: usize = 32;
+    pub const MAX_LEN: usize = ObjectId::SHA256_LEN;
+
+    /// Build an id from its raw bytes, None unless the length is that of a sha1 or sha256 id.
+    pub fn from_bytes(bytes: &[u8]) -> Option<ObjectId> {
+        if bytes.len() != Self::SHA1_LEN && bytes.len() != Self::SHA256_LEN {
+            return None;
+        }
+
+        let mut id = ObjectId {
+            bytes: [0; Self::MAX_LEN],
+            len: bytes.len() as u8,
+        };
+        id.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(id)
+    }
+
+    /// Parse an id from hex, upper or lower case. None if the hex is not a whole sha1 or
+    /// sha256 id.
+    pub fn from_hex(hex: &str) -> Option<ObjectId> {
+        let hex = hex.as_bytes();
+        let len = hex.len() / 2;
+        if !hex.len().is_multiple_of(2) || (len != Self::SHA1_LEN && len != Self::SHA256_LEN) {
+            return None;
+        }
+
+        let mut id = ObjectId {
+            bytes: [0; Self::MAX_LEN],
+            len: len as u8,
+        };
+        for (byte, pair) in id.bytes.iter_mut().zip(hex.chunks_exact(2)) {
+            *byte = (hex_value(pair[0])? << 4) | hex_value(pair[1])?;
+        }
+        Some(id)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// The length of the id in bytes.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// True for an id made up of only zeros, which git uses to mean no object.
+    pub fn is_null(&self) -> bool {
+        self.as_bytes().iter().all(|&b| b == 0)
+    }
+
+    /// The object format the id belongs to, from its length.
+    pub fn format(&self) -> ObjectFormat {
+        if self.len() == Self::SHA256_LEN {
+            ObjectFormat::Sha256
+        } else {
+            ObjectFormat::Sha1
+        }
+    }
+
+    /// The lower case hex form of the id, as git displays it.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// The hash function a repo uses for its object ids, set by `extensions.objectFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// Parse the name git uses for an object format, `sha1` or `sha256`.
+    pub fn from_name(name: &str) -> Option<ObjectFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Some(ObjectFormat::Sha1),
+            "sha256" => Some(ObjectFormat::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// The length in bytes of an object id.
+    pub fn hash_len(&self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => ObjectId::SHA1_LEN,
+            ObjectFormat::Sha256 => ObjectId::SHA256_LEN,
+        }
+    }
+
+    /// The length of an object id written out as hex.
+    pub fn hex_len(&self) -> usize {
+        self.hash_len() * 2
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Only the used bytes are hashed, which keeps it consistent with Eq as the rest are zero.
+impl Hash for ObjectId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write(self.as_bytes());
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.as_bytes() {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({self})")
+    }
+}
+
+/// Returned when a string is not a valid hex object id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseObjectIdError(String);
+
+impl fmt::Display for ParseObjectIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid object id: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseObjectIdError {}
+
+impl FromStr for ObjectId {
+    type Err = ParseObjectIdError;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        ObjectId::from_hex(hex).ok_or_else(|| ParseObjectIdError(hex.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn test_object_id_hex_round_trip() {
+        let id = ObjectId::from_hex(SHA1).unwrap();
+        assert_eq!(id.len(), ObjectId::SHA1_LEN);
+        assert_eq!(id.to_string(), SHA1);
+        assert_eq!(id.as_bytes()[..2], [0x01, 0x23]);
+        assert_eq!(ObjectId::from_hex(&SHA1.to_uppercase()), Some(id));
+        assert_eq!(SHA1.parse::<ObjectId>(), Ok(id));
+
+        let sha256 = "ab".repeat(32);
+        let id = ObjectId::from_hex(&sha256).unwrap();
+        assert_eq!(id.len(), ObjectId::SHA256_LEN);
+        assert_eq!(id.to_hex(), sha256);
+        assert_eq!(id.format(), ObjectFormat::Sha256);
+    }
+
+    #[test]
+    fn test_object_format() {
+        assert_eq!(
+            ObjectFormat::from_name("SHA256"),
+            Some(ObjectFormat::Sha256)
+        );
+        assert_eq!(ObjectFormat::from_name("sha1"), Some(ObjectFormat::Sha1));
+        assert!(ObjectFormat::from_name("md5").is_none());
+        assert_eq!(ObjectFormat::Sha1.hex_len(), 40);
+        assert_eq!(ObjectFormat::Sha256.hex_len(), 64);
+    }
+
+    #[test]
+    fn test_object_id_rejects_bad_hex() {
+        assert!(ObjectId::from_hex("").is_none());
+        assert!(ObjectId::from_hex("0123").is_none());
+        assert!(ObjectId::from_hex(&SHA1[..39]).is_none());
+        assert!(ObjectId::from_hex(&SHA1.replace('a', "g")).is_none());
+        assert!("mock_hash".parse::<ObjectId>().is_err());
+        assert!(ObjectId::from_bytes(&[0; 16]).is_none());
+    }
+
+    #[test]
+    fn test_object_id_null() {
+        assert!(ObjectId::from_hex(&"0".repeat(40)).unwrap().is_null());
+        assert!(!ObjectId::from_hex(SHA1).unwrap().is_null());
+    }
+}
@@ -0,0 +1,418 @@
+use crate::error::{Error, Result};
+use crate::object_id::ObjectId;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+/// The four kinds of objects stored in a git object database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl ObjectKind {
+    /// Parse the type name git uses for an object, `commit`, `tree`, `blob` or `tag`.
+    pub fn from_name(name: &str) -> Option<ObjectKind> {
+        match name {
+            "commit" => Some(ObjectKind::Commit),
+            "tree" => Some(ObjectKind::Tree),
+            "blob" => Some(ObjectKind::Blob),
+            "tag" => Some(ObjectKind::Tag),
+            _ => None,
+        }
+    }
+}
+
+/// Which part of the history a source reads. By default every ref is read, as with
+/// `git rev-list --all`. Otherwise only what is reachable from the given refs and ranges,
+/// which is what a clone of those refs would fetch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scope {
+    refs: Vec<String>,
+    ranges: Vec<String>,
+}
+
+impl Scope {
+    /// Every ref in the repo.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only what is reachable from `refs`, branch or tag names such as `main`, or glob
+    /// patterns such as `release/*`, and from `ranges` such as `v1.0..v2.0`. The excluded
+    /// side of a range applies to everything, as it does with `git rev-list`. Both empty
+    /// means every ref.
+    pub fn new(refs: &[String], ranges: &[String]) -> Result<Self> {
+        // Anything starting with a dash would be taken by git as an option.
+        if let Some(bad) = refs
+            .iter()
+            .chain(ranges)
+            .find(|r| r.is_empty() || r.starts_with('-'))
+        {
+            return Err(Error::malformed("revision", bad));
+        }
+
+        Ok(Scope {
+            refs: refs.to_vec(),
+            ranges: ranges.to_vec(),
+        })
+    }
+
+    /// True when every ref is read.
+    pub fn is_all(&self) -> bool {
+        self.refs.is_empty() && self.ranges.is_empty()
+    }
+
+    pub fn refs(&self) -> &[String] {
+        &self.refs
+    }
+
+    pub fn ranges(&self) -> &[String] {
+        &self.ranges
+    }
+
+    /// The revision arguments that select this scope for `git rev-list` and `git log`. Glob
+    /// patterns are matched against branches, tags and remote branches, or against the full
+    /// ref name when they start with `refs/`.
+    pub fn rev_list_args(&self) -> Vec<String> {
+        if self.is_all() {
+            return vec!["--all".to_string()];
+        }
+
+        let mut args = Vec::new();
+        for name in &self.refs {
+            if !is_glob(name) {
+                args.push(name.clone());
+            } else if name.starts_with("refs/") {
+                args.push(format!("--glob={name}"));
+            } else {
+                args.push(format!("--branches={name}"));
+                args.push(format!("--tags={name}"));
+                args.push(format!("--remotes={name}"));
+            }
+        }
+        args.extend(self.ranges.iter().cloned());
+        args
+    }
+}
+
+/// True if a ref name is a glob pattern rather than a single ref.
+pub fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?', '['])
+}
+
+/// Add a line for the object `hash` at `path` to the deps of a commit, in the form used by
+/// `ObjectSource::commit_deps`. Backslashes and newlines in the path are escaped so each
+/// object stays on a line of its own.
+pub fn push_dep(deps: &mut String, hash: impl Display, path: &str) {
+    deps.push_str(&hash.to_string());
+    deps.push(' ');
+    for c in path.chars() {
+        match c {
+            '\\' => deps.push_str("\\\\"),
+            '\n' => deps.push_str("\\n"),
+            c => deps.push(c),
+        }
+    }
+    deps.push('\n');
+}
+
+/// Split a line written by `push_dep` back into the object hash and its path.
+pub fn split_dep(line: &str) -> (&str, Cow<'_, str>) {
+    let (hash, path) = line.split_once(' ').unwrap_or((line, ""));
+    if !path.contains('\\') {
+        return (hash, Cow::Borrowed(path));
+    }
+
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    (hash, Cow::Owned(unescaped))
+}
+
+/// A single object as listed by a source, its type, id and sizes. If the object is stored
+/// as a delta `delta_base` is the id of the object it is a delta against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectRecord {
+    pub kind: ObjectKind,
+    pub hash: ObjectId,
+    pub size: u64,
+    pub size_disk: u64,
+    pub delta_base: Option<ObjectId>,
+}
+
+/// Somewhere the objects of a repo can be read from. The processing functions only talk to
+/// the repo through this trait, so the container can be populated from the git cli, libgit2
+/// or an in memory fake for tests.
+pub trait ObjectSource: Send + Sync {
+    /// Call `on_object` with every object in the repo along with its type and sizes. Objects
+    /// are handed over as they are read so the full list never has to be held in memory.
+    fn objects(&self, on_object: &mut dyn FnMut(ObjectRecord)) -> Result<()>;
+
+    /// List the objects introduced by a commit. One object per line in the same form as
+    /// `git rev-list --objects`, the hash optionally followed by a space and the path, as
+    /// written by `push_dep`. The commit itself is not included.
+    fn commit_deps(&self, commit_hash: &str) -> Result<String>;
+
+    /// True if `stream_commit_deps` reads many commits at once and should be preferred over
+    /// calling `commit_deps` for each commit.
+    fn streams_commit_deps(&self) -> bool {
+        false
+    }
+
+    /// Read the deps of every commit in `commits`, calling `on_deps` with the commit hash and
+    /// its deps (in the same form as `commit_deps`) as each one is read. `on_deps` may be
+    /// called from more than one thread at a time.
+    fn stream_commit_deps(
+        &self,
+        commits: &[String],
+        on_deps: &(dyn Fn(&str, String) + Sync),
+    ) -> Result<()> {
+        for commit_hash in commits {
+            on_deps(commit_hash, self.commit_deps(commit_hash)?);
+        }
+        Ok(())
+    }
+
+    /// List every commit along with its parents in the same form as
+    /// `git rev-list --parents`. One `<commit> [<parent>...]` per line, first parent first.
+    fn commit_parents(&self) -> Result<String>;
+
+    /// List the author, dates and subject of every commit in the same form as
+    /// `git log --format=%H%x00%an%x00%ae%x00%at%x00%ct%x00%s`. One commit per line with the
+    /// fields separated by a nul, the dates are seconds since the unix epoch.
+    fn commit_metadata(&self) -> Result<String>;
+
+    /// List the tag refs in the same form as `git show-ref --tags -d`. One `<hash> <ref>`
+    /// per line, annotated tags are followed by a `<hash> <ref>^{}` line for the commit
+    /// they point at.
+    fn tag_refs(&self) -> Result<String>;
+
+    /// List every ref, along with `HEAD`, in the same form as `git show-ref --head`. One
+    /// `<hash> <ref>` per line. Used to find what each branch and tag keeps alive.
+    fn ref_tips(&self) -> Result<String>;
+
+    /// The repo being read, if it is on disk. Used to tell caches for different repos apart.
+    fn repo_path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// An object source backed entirely by memory. Used to drive the processing and reports
+/// without needing a real repo.
+#[derive(Default)]
+pub struct InMemorySource {
+    objects: Vec<ObjectRecord>,
+    commit_deps: HashMap<String, Vec<(String, String)>>,
+    commit_parents: Vec<(String, Vec<String>)>,
+    commit_metadata: Vec<String>,
+    tag_refs: Vec<(String, String)>,
+    refs: Vec<(String, String)>,
+}
+
+impl InMemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an object, `hash` must be a valid hex object id.
+    pub fn add_object(&mut self, kind: ObjectKind, hash: &str, size: u64, size_disk: u64) {
+        self.objects.push(ObjectRecord {
+            kind,
+            hash: parse_id(hash),
+            size,
+            size_disk,
+            delta_base: None,
+        });
+    }
+
+    /// Mark the object `hash` as stored as a delta against `base_hash`.
+    pub fn set_delta_base(&mut self, hash: &str, base_hash: &str) {
+        let hash = parse_id(hash);
+        if let Some(object) = self.objects.iter_mut().find(|o| o.hash == hash) {
+            object.delta_base = Some(parse_id(base_hash));
+        }
+    }
+
+    /// Record that `commit_hash` introduced the object `hash` at `path`. The root tree of a
+    /// commit has an empty path.
+    pub fn add_commit_dep(&mut self, commit_hash: &str, hash: &str, path: &str) {
+        self.commit_deps
+            .entry(commit_hash.to_string())
+            .or_default()
+            .push((hash.to_string(), path.to_string()));
+    }
+
+    /// Record the parents of a commit, first parent first.
+    pub fn add_commit_parents(&mut self, commit_hash: &str, parents: &[&str]) {
+        self.commit_parents.push((
+            commit_hash.to_string(),
+            parents.iter().map(|p| p.to_string()).collect(),
+        ));
+    }
+
+    /// Record who made a commit and when, times are seconds since the unix epoch.
+    pub fn add_commit_metadata(
+        &mut self,
+        commit_hash: &str,
+        author: (&str, &str),
+        author_time: i64,
+        commit_time: i64,
+        subject: &str,
+    ) {
+        self.commit_metadata.push(format!(
+            "{commit_hash}\0{}\0{}\0{author_time}\0{commit_time}\0{subject}",
+            author.0, author.1
+        ));
+    }
+
+    /// Add a tag ref pointing at `hash`. For annotated tags `hash` is the tag object and
+    /// `commit_hash` the commit it points at, lightweight tags have no `commit_hash`.
+    pub fn add_tag_ref(&mut self, name: &str, hash: &str, commit_hash: Option<&str>) {
+        let ref_name = format!("refs/tags/{name}");
+        self.tag_refs.push((hash.to_string(), ref_name.clone()));
+        if let Some(commit_hash) = commit_hash {
+            self.tag_refs
+                .push((commit_hash.to_string(), format!("{ref_name}^{{}}")));
+        }
+    }
+
+    /// Add a ref other than a tag, `name` is the full ref name such as `refs/heads/main`.
+    pub fn add_ref(&mut self, name: &str, hash: &str) {
+        self.refs.push((hash.to_string(), name.to_string()));
+    }
+
+    /// Read everything added to the source into a new container, as a full run does: the
+    /// objects, commit deps, parents, metadata, tags and then refs.
+    #[cfg(test)]
+    pub async fn into_container(self) -> crate::object_collection::ObjectContainer {
+        use crate::git_processing::{
+            process_all_commit_deps, process_commit_metadata, process_commit_parents,
+            process_initial_repo, process_refs, process_tags,
+        };
+
+        let source = std::sync::Arc::new(self);
+        let mut container = crate::object_collection::ObjectContainer::new();
+        process_initial_repo(source.as_ref(), &mut container).unwrap();
+        process_all_commit_deps(source.clone(), &container, &None)
+            .await
+            .unwrap();
+        process_commit_parents(source.as_ref(), &container).unwrap();
+        process_commit_metadata(source.as_ref(), &container).unwrap();
+        process_tags(source.as_ref(), &container).unwrap();
+        process_refs(source.as_ref(), &container).unwrap();
+        container
+    }
+}
+
+// The in memory source is only fed by tests, so a bad id is a bug in the test.
+fn parse_id(hash: &str) -> ObjectId {
+    ObjectId::from_hex(hash).unwrap_or_else(|| panic!("invalid object id: {hash}"))
+}
+
+impl ObjectSource for InMemorySource {
+    fn objects(&self, on_object: &mut dyn FnMut(ObjectRecord)) -> Result<()> {
+        self.objects.iter().cloned().for_each(on_object);
+        Ok(())
+    }
+
+    fn commit_deps(&self, commit_hash: &str) -> Result<String> {
+        let mut deps = String::new();
+        if let Some(objects) = self.commit_deps.get(commit_hash) {
+            for (hash, path) in objects {
+                push_dep(&mut deps, hash, path);
+            }
+        }
+        Ok(deps)
+    }
+
+    fn commit_parents(&self) -> Result<String> {
+        let lines: Vec<String> = self
+            .commit_parents
+            .iter()
+            .map(|(hash, parents)| {
+                let mut line = hash.clone();
+                for parent in parents {
+                    line += &format!(" {parent}");
+                }
+                line
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn commit_metadata(&self) -> Result<String> {
+        Ok(self.commit_metadata.join("\n"))
+    }
+
+    fn tag_refs(&self) -> Result<String> {
+        let lines: Vec<String> = self
+            .tag_refs
+            .iter()
+            .map(|(hash, name)| format!("{hash} {name}"))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn ref_tips(&self) -> Result<String> {
+        let lines: Vec<String> = self
+            .refs
+            .iter()
+            .chain(
+                self.tag_refs
+                    .iter()
+                    .filter(|(_, name)| !name.ends_with("^{}")),
+            )
+            .map(|(hash, name)| format!("{hash} {name}"))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_rev_list_args() {
+        assert!(Scope::all().is_all());
+        assert_eq!(Scope::all().rev_list_args(), vec!["--all"]);
+
+        let scope = Scope::new(
+            &["main".to_string(), "release/*".to_string()],
+            &["v1.0..v2.0".to_string()],
+        )
+        .unwrap();
+        assert!(!scope.is_all());
+        assert_eq!(
+            scope.rev_list_args(),
+            vec![
+                "main",
+                "--branches=release/*",
+                "--tags=release/*",
+                "--remotes=release/*",
+                "v1.0..v2.0"
+            ]
+        );
+
+        let scope = Scope::new(&["refs/notes/*".to_string()], &[]).unwrap();
+        assert_eq!(scope.rev_list_args(), vec!["--glob=refs/notes/*"]);
+
+        assert!(Scope::new(&["--output=x".to_string()], &[]).is_err());
+        assert!(Scope::new(&[], &[String::new()]).is_err());
+    }
+}
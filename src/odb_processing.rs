@@ -0,0 +1,605 @@
+use crate::error::{Error, Result};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_source::{is_glob, push_dep, ObjectKind, ObjectRecord, ObjectSource, Scope};
+use crate::pack::read_object_format;
+use git2::{ObjectType, Oid, Repository, Tree};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Reads the repo in process with libgit2 rather than running the git cli. Objects are
+/// listed by walking the commits reachable from the refs along with their trees, the same
+/// objects `git rev-list --objects --all` lists, and reading each object header, so the
+/// object list is never buffered as text.
+///
+/// The object database is walked from the refs rather than visited with `odb.foreach`, which
+/// would also list unreachable and dangling objects and give different totals to the other
+/// backends.
+///
+/// libgit2 does not expose the compressed size of an object or how it is stored, so the on
+/// disk size is reported as the inflated size and no delta information is available.
+pub struct Libgit2Source {
+    repo_path: PathBuf,
+    scope: Scope,
+}
+
+impl Libgit2Source {
+    pub fn new(repo_path: &Path) -> Self {
+        Libgit2Source {
+            repo_path: repo_path.to_path_buf(),
+            scope: Scope::all(),
+        }
+    }
+
+    /// Only read the objects and commits in `scope`, the same objects
+    /// `git rev-list --objects` lists for it.
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    // git2::Repository is not Sync, so each call opens its own handle. This keeps the source
+    // usable from the worker tasks that build commit deps.
+    fn open(&self) -> Result<Repository> {
+        // libgit2 can not read sha256 repos, say so rather than failing with a config error.
+        if read_object_format(&self.repo_path)? != ObjectFormat::Sha1 {
+            return Err(Error::Unsupported(
+                "the libgit2 backend only supports sha1 repos, use the git or pack backend"
+                    .to_string(),
+            ));
+        }
+        Repository::open(&self.repo_path).map_err(|e| match e.code() {
+            git2::ErrorCode::NotFound => Error::NotARepo(self.repo_path.clone()),
+            _ => e.into(),
+        })
+    }
+}
+
+impl ObjectSource for Libgit2Source {
+    fn objects(&self, on_object: &mut dyn FnMut(ObjectRecord)) -> Result<()> {
+        let repo = self.open()?;
+        let odb = repo.odb()?;
+
+        // Only reachable objects are listed, as with the git backend, so unreachable and
+        // dangling objects left in the odb do not skew the totals.
+        let mut oids: Vec<Oid> = Vec::new();
+        let mut seen: HashSet<Oid> = HashSet::new();
+        scoped_objects(&repo, &self.scope, &mut seen, &mut oids)?;
+
+        for oid in oids {
+            let (size, kind) = odb.read_header(oid)?;
+            let size = size as u64;
+            let kind = match kind {
+                ObjectType::Commit => ObjectKind::Commit,
+                ObjectType::Tree => ObjectKind::Tree,
+                ObjectType::Blob => ObjectKind::Blob,
+                ObjectType::Tag => ObjectKind::Tag,
+                _ => return Err(Error::malformed("object type", &format!("{oid} {kind:?}"))),
+            };
+
+            let Some(hash) = ObjectId::from_bytes(oid.as_bytes()) else {
+                continue;
+            };
+            on_object(ObjectRecord {
+                kind,
+                hash,
+                size,
+                size_disk: size,
+                delta_base: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Compares the commit tree against the tree of its first parent and lists every tree
+    /// and blob that is new or changed, along with the root tree. Unlike
+    /// `git rev-list <c>~1..<c>` an object moved from elsewhere in the tree is listed again.
+    fn commit_deps(&self, commit_hash: &str) -> Result<String> {
+        let repo = self.open()?;
+        let commit = repo.find_commit(Oid::from_str(commit_hash)?)?;
+        let tree = commit.tree()?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+
+        let mut deps = String::new();
+        if parent_tree.as_ref().map(Tree::id) != Some(tree.id()) {
+            push_dep(&mut deps, tree.id(), "");
+            diff_trees(&repo, parent_tree.as_ref(), &tree, "", &mut deps)?;
+        }
+
+        Ok(deps)
+    }
+
+    fn commit_parents(&self) -> Result<String> {
+        let repo = self.open()?;
+        let mut lines: Vec<String> = Vec::new();
+        for oid in walk_commits(&repo, &scope_tips(&repo, &self.scope)?)? {
+            let commit = repo.find_commit(oid?)?;
+            let mut line = commit.id().to_string();
+            for parent in commit.parent_ids() {
+                line += &format!(" {parent}");
+            }
+            lines.push(line);
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn commit_metadata(&self) -> Result<String> {
+        let repo = self.open()?;
+        let mut lines: Vec<String> = Vec::new();
+        for oid in walk_commits(&repo, &scope_tips(&repo, &self.scope)?)? {
+            let commit = repo.find_commit(oid?)?;
+            let author = commit.author();
+            lines.push(format!(
+                "{}\0{}\0{}\0{}\0{}\0{}",
+                commit.id(),
+                String::from_utf8_lossy(author.name_bytes()),
+                String::from_utf8_lossy(author.email_bytes()),
+                author.when().seconds(),
+                commit.committer().when().seconds(),
+                commit.summary().unwrap_or_default(),
+            ));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn tag_refs(&self) -> Result<String> {
+        let repo = self.open()?;
+        let mut lines: Vec<String> = Vec::new();
+
+        for reference in repo.references_glob("refs/tags/*")? {
+            let reference = reference?;
+            let (Some(name), Some(target)) = (reference.name(), reference.target()) else {
+                continue;
+            };
+
+            lines.push(format!("{target} {name}"));
+            if let Ok(tag) = repo.find_tag(target) {
+                let peeled = tag.peel()?;
+                lines.push(format!("{} {name}^{{}}", peeled.id()));
+            }
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn ref_tips(&self) -> Result<String> {
+        let repo = self.open()?;
+        let mut lines: Vec<String> = Vec::new();
+
+        if let Ok(head) = repo.head() {
+            if let Some(target) = head.target() {
+                lines.push(format!("{target} HEAD"));
+            }
+        }
+        for reference in repo.references()? {
+            let reference = reference?;
+            if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+                lines.push(format!("{target} {name}"));
+            }
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn repo_path(&self) -> Option<&Path> {
+        Some(&self.repo_path)
+    }
+}
+
+// What a scope starts from, as `git rev-list` sees it. Annotated tags are peeled, down a
+// chain of tags if need be, to the commit, tree or blob they point at. Commits are walked,
+// while trees and blobs a ref points at directly are listed as they are. The excluded side
+// of a range is kept apart in `hidden_commits`.
+#[derive(Default)]
+struct Tips {
+    tags: Vec<Oid>,
+    commits: Vec<Oid>,
+    hidden_commits: Vec<Oid>,
+    objects: Vec<Oid>,
+}
+
+impl Tips {
+    fn add(&mut self, repo: &Repository, oid: Oid, hidden: bool) -> Result<()> {
+        let mut object = repo.find_object(oid, None)?;
+        while let Some(tag) = object.as_tag() {
+            if !hidden {
+                self.tags.push(tag.id());
+            }
+            let target = tag.target_id();
+            object = repo.find_object(target, None)?;
+        }
+
+        match (object.kind(), hidden) {
+            (Some(ObjectType::Commit), true) => self.hidden_commits.push(object.id()),
+            (Some(ObjectType::Commit), false) => self.commits.push(object.id()),
+            (_, false) => self.objects.push(object.id()),
+            (_, true) => {}
+        }
+        Ok(())
+    }
+
+    fn add_refs(&mut self, repo: &Repository, glob: &str) -> Result<()> {
+        for reference in repo.references_glob(glob)? {
+            if let Some(target) = reference?.resolve()?.target() {
+                self.add(repo, target, false)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The tips of every ref in scope. With no scope that is every ref and `HEAD`, like
+// `git rev-list --all`.
+fn scope_tips(repo: &Repository, scope: &Scope) -> Result<Tips> {
+    let mut tips = Tips::default();
+    if scope.is_all() {
+        if let Some(head) = repo.head().ok().and_then(|head| head.target()) {
+            tips.add(repo, head, false)?;
+        }
+        tips.add_refs(repo, "refs/*")?;
+        return Ok(tips);
+    }
+
+    for name in scope.refs() {
+        if !is_glob(name) {
+            tips.add(repo, repo.revparse_single(name)?.id(), false)?;
+        } else if name.starts_with("refs/") {
+            tips.add_refs(repo, name)?;
+        } else {
+            for prefix in ["refs/heads/", "refs/tags/", "refs/remotes/"] {
+                tips.add_refs(repo, &format!("{prefix}{name}"))?;
+            }
+        }
+    }
+    for range in scope.ranges() {
+        let spec = repo.revparse(range)?;
+        let (from, to) = (
+            spec.from().map(git2::Object::id),
+            spec.to().map(git2::Object::id),
+        );
+        if spec.mode().contains(git2::RevparseMode::MERGE_BASE) {
+            // `a...b` is everything reachable from either side but not from both.
+            if let (Some(from), Some(to)) = (from, to) {
+                tips.add(repo, from, false)?;
+                tips.add(repo, to, false)?;
+                let (a, b) = (repo.find_object(from, None)?, repo.find_object(to, None)?);
+                let base = repo.merge_base(a.peel_to_commit()?.id(), b.peel_to_commit()?.id())?;
+                tips.add(repo, base, true)?;
+            }
+        } else if spec.mode().contains(git2::RevparseMode::RANGE) {
+            if let (Some(from), Some(to)) = (from, to) {
+                tips.add(repo, from, true)?;
+                tips.add(repo, to, false)?;
+            }
+        } else if let Some(from) = from {
+            tips.add(repo, from, false)?;
+        }
+    }
+    Ok(tips)
+}
+
+// Walk every commit reachable from the tips, like `git rev-list`.
+fn walk_commits<'a>(repo: &'a Repository, tips: &Tips) -> Result<git2::Revwalk<'a>> {
+    let mut walk = repo.revwalk()?;
+    for commit in &tips.commits {
+        walk.push(*commit)?;
+    }
+    for commit in &tips.hidden_commits {
+        walk.hide(*commit)?;
+    }
+    Ok(walk)
+}
+
+// Collect every object in scope, like `git rev-list --objects`. The annotated tags on the
+// way to each tip, the commits along with the trees and blobs they reference, and any tree
+// or blob a ref points at directly. Objects already in `seen` are skipped, and a tree that
+// has been seen is not walked again.
+fn scoped_objects(
+    repo: &Repository,
+    scope: &Scope,
+    seen: &mut HashSet<Oid>,
+    oids: &mut Vec<Oid>,
+) -> Result<()> {
+    let tips = scope_tips(repo, scope)?;
+    for tag in &tips.tags {
+        if seen.insert(*tag) {
+            oids.push(*tag);
+        }
+    }
+
+    let commits = walk_commits(repo, &tips)?.collect::<std::result::Result<Vec<Oid>, _>>()?;
+
+    // The trees of the commits on the edge of a range are left out, along with everything
+    // in them, as `git rev-list --objects a..b` does. Otherwise every blob in the tree of
+    // the first commit in range would be listed, not just the ones the range changed.
+    if !tips.hidden_commits.is_empty() {
+        let walked: HashSet<&Oid> = commits.iter().collect();
+        let mut edges: Vec<Oid> = tips.hidden_commits.clone();
+        for oid in &commits {
+            let commit = repo.find_commit(*oid)?;
+            edges.extend(
+                commit
+                    .parent_ids()
+                    .filter(|parent| !walked.contains(parent)),
+            );
+        }
+
+        let mut excluded: Vec<Oid> = Vec::new();
+        for edge in edges {
+            seen.insert(edge);
+            collect_tree(repo, repo.find_commit(edge)?.tree_id(), seen, &mut excluded)?;
+        }
+    }
+
+    for oid in commits {
+        let commit = repo.find_commit(oid)?;
+        if seen.insert(commit.id()) {
+            oids.push(commit.id());
+        }
+        collect_tree(repo, commit.tree_id(), seen, oids)?;
+    }
+
+    for oid in &tips.objects {
+        match repo.find_object(*oid, None)?.kind() {
+            Some(ObjectType::Tree) => collect_tree(repo, *oid, seen, oids)?,
+            _ if seen.insert(*oid) => oids.push(*oid),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn collect_tree(
+    repo: &Repository,
+    tree_id: Oid,
+    seen: &mut HashSet<Oid>,
+    oids: &mut Vec<Oid>,
+) -> Result<()> {
+    if !seen.insert(tree_id) {
+        return Ok(());
+    }
+    oids.push(tree_id);
+
+    let tree = repo.find_tree(tree_id)?;
+    for entry in &tree {
+        match entry.kind() {
+            Some(ObjectType::Tree) => collect_tree(repo, entry.id(), seen, oids)?,
+            Some(ObjectType::Blob) if seen.insert(entry.id()) => oids.push(entry.id()),
+            // submodule commits live in another repo
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Walk `tree` and write out every entry that does not appear with the same id at the same
+// path in `old_tree`. Subtrees that are unchanged are skipped entirely.
+fn diff_trees(
+    repo: &Repository,
+    old_tree: Option<&Tree>,
+    tree: &Tree,
+    prefix: &str,
+    deps: &mut String,
+) -> Result<()> {
+    for entry in tree {
+        let Some(name) = entry.name() else {
+            continue;
+        };
+
+        let old_entry = old_tree.and_then(|old| old.get_name(name));
+        if old_entry.as_ref().map(git2::TreeEntry::id) == Some(entry.id()) {
+            continue;
+        }
+
+        let path = format!("{prefix}{name}");
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                push_dep(deps, entry.id(), &path);
+                let subtree = repo.find_tree(entry.id())?;
+                let old_subtree = match old_entry {
+                    Some(old) if old.kind() == Some(ObjectType::Tree) => {
+                        Some(repo.find_tree(old.id())?)
+                    }
+                    _ => None,
+                };
+                diff_trees(
+                    repo,
+                    old_subtree.as_ref(),
+                    &subtree,
+                    &format!("{path}/"),
+                    deps,
+                )?;
+            }
+            Some(ObjectType::Blob) => {
+                push_dep(deps, entry.id(), &path);
+            }
+            // submodule commits live in another repo
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_processing::run_command;
+    use crate::git_commands::GitCliSource;
+    use crate::git_processing::{
+        process_all_commit_deps, process_commit_parents, process_initial_repo, process_tags,
+    };
+    use crate::object_collection::ObjectContainer;
+    use git2::Signature;
+    use std::{fs, sync::Arc};
+    use tempfile::TempDir;
+
+    // The sorted ids the source lists.
+    fn listed_ids(source: &dyn ObjectSource) -> Vec<String> {
+        let mut ids: Vec<String> = Vec::new();
+        source
+            .objects(&mut |record| ids.push(record.hash.to_string()))
+            .unwrap();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_libgit2_source() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let sig = Signature::now("Test", "test@example.com").unwrap();
+
+        let first_blob = repo.blob(b"hello world\n").unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("hello.txt", first_blob, 0o100644).unwrap();
+        let first_tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let first = repo
+            .commit(Some("HEAD"), &sig, &sig, "initial", &first_tree, &[])
+            .unwrap();
+        let first = repo.find_commit(first).unwrap();
+
+        let second_blob = repo.blob(b"goodbye\n").unwrap();
+        builder.insert("bye.txt", second_blob, 0o100644).unwrap();
+        let second_tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let second = repo
+            .commit(Some("HEAD"), &sig, &sig, "second", &second_tree, &[&first])
+            .unwrap();
+        let second = repo.find_object(second, None).unwrap();
+        repo.tag("v1.0", &second, &sig, "release", false).unwrap();
+        // Dangling, nothing refers to it so it is not listed.
+        repo.blob(b"dangling\n").unwrap();
+
+        let source = Arc::new(Libgit2Source::new(dir.path()));
+        let mut container = ObjectContainer::new();
+        process_initial_repo(source.as_ref(), &mut container).unwrap();
+
+        assert_eq!(container.commits().count(), 2);
+        assert_eq!(container.trees().count(), 2);
+        assert_eq!(container.blobs().count(), 2);
+        assert_eq!(container.tags().count(), 1);
+
+        let blob = container
+            .blobs()
+            .get_by_hex(&first_blob.to_string())
+            .unwrap();
+        assert_eq!(blob.read().unwrap().size_disk(), 12);
+
+        process_all_commit_deps(source.clone(), &container, &None)
+            .await
+            .unwrap();
+        process_commit_parents(source.as_ref(), &container).unwrap();
+        process_tags(source.as_ref(), &container).unwrap();
+
+        // The second commit only introduced the new blob and the new root tree.
+        let commit = container
+            .commits()
+            .get_by_hex(&second.id().to_string())
+            .unwrap()
+            .read()
+            .unwrap();
+        assert_eq!(commit.blob_deps().len(), 1);
+        assert_eq!(commit.tree_deps().len(), 1);
+        assert_eq!(commit.tag_deps().len(), 1);
+
+        let first_index = container
+            .commits()
+            .get_index_by_hex(&first.id().to_string());
+        assert_eq!(commit.parents(), &vec![*first_index.unwrap()]);
+
+        // Scoped to the first commit only its tree and blob are read, and not the tag.
+        let scope = Scope::new(&[first.id().to_string()], &[]).unwrap();
+        let source = Libgit2Source::new(dir.path()).with_scope(scope);
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+        assert_eq!(container.commits().count(), 1);
+        assert_eq!(container.trees().count(), 1);
+        assert_eq!(container.blobs().count(), 1);
+        assert_eq!(container.tags().count(), 0);
+    }
+
+    #[test]
+    fn test_libgit2_source_matches_rev_list() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path();
+        let git = |args: &[&str]| run_command(repo, "git", args).unwrap();
+        git(&["init", "-q", "-b", "main"]);
+        git(&["config", "user.email", "test@example.com"]);
+        git(&["config", "user.name", "Test"]);
+
+        fs::create_dir(repo.join("dir")).unwrap();
+        fs::write(repo.join("dir/a.txt"), "a\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "first"]);
+        git(&["tag", "-a", "v1", "-m", "v1"]);
+        // A tag of a tag, only reachable by peeling the outer one.
+        git(&["tag", "-a", "nested", "-m", "nested", "v1"]);
+
+        fs::write(repo.join("b.txt"), "b\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "second"]);
+
+        // A tag on a blob that is in no commit, and refs straight at a tree and a blob.
+        fs::write(repo.join("loose.txt"), "loose\n").unwrap();
+        let loose = git(&["hash-object", "-w", "loose.txt"]);
+        git(&["tag", "-a", "blobtag", "-m", "blob", loose.trim()]);
+        git(&["update-ref", "refs/trees/root", "HEAD~1^{tree}"]);
+        git(&["update-ref", "refs/blobs/a", "HEAD:dir/a.txt"]);
+
+        // An annotated tag only kept alive by a ref outside refs/tags.
+        git(&["tag", "-a", "archived", "-m", "archived", "HEAD~1"]);
+        let archived = git(&["rev-parse", "archived"]);
+        git(&["update-ref", "refs/archive/archived", archived.trim()]);
+        git(&["tag", "-d", "archived"]);
+
+        let mut expected: Vec<String> =
+            git(&["rev-list", "--objects", "--all", "--no-object-names"])
+                .lines()
+                .map(str::to_string)
+                .collect();
+        expected.sort();
+
+        assert_eq!(listed_ids(&Libgit2Source::new(repo)), expected);
+    }
+
+    #[test]
+    fn test_libgit2_source_range_matches_git() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path();
+        let git = |args: &[&str]| run_command(repo, "git", args).unwrap();
+        git(&["init", "-q", "-b", "main"]);
+        git(&["config", "user.email", "test@example.com"]);
+        git(&["config", "user.name", "Test"]);
+
+        fs::create_dir(repo.join("dir")).unwrap();
+        fs::write(repo.join("dir/a.txt"), "a\n").unwrap();
+        fs::write(repo.join("b.txt"), "b\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "first"]);
+        git(&["tag", "v1"]);
+
+        // A side branch merged back in, so the range has more than one edge.
+        git(&["checkout", "-q", "-b", "side"]);
+        fs::write(repo.join("side.txt"), "side\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "side"]);
+        git(&["checkout", "-q", "main"]);
+        fs::write(repo.join("dir/a.txt"), "a changed\n").unwrap();
+        git(&["commit", "-q", "-am", "second"]);
+        git(&["merge", "-q", "--no-edit", "side"]);
+
+        for range in ["v1..main", "side..main", "main~1...side"] {
+            let scope = || Scope::new(&[], &[range.to_string()]).unwrap();
+            let expected = listed_ids(&GitCliSource::new(repo).with_scope(scope()));
+            let listed = listed_ids(&Libgit2Source::new(repo).with_scope(scope()));
+            assert_eq!(listed, expected, "{range}");
+        }
+    }
+}
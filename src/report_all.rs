@@ -1,10 +1,159 @@
 use crate::object_collection::ObjectContainer;
-use crate::report_blobs::report_blobs;
-use crate::report_commits::report_commits;
-use crate::report_trees::report_trees;
+use crate::report_blobs::{build_blob_report, print_blob_report, BlobReport};
+use crate::report_branches::{build_branch_report, print_branch_report, BranchReport};
+use crate::report_commits::{build_commit_report, print_commit_report, CommitReport};
+use crate::report_deltas::{build_delta_report, print_delta_report, DeltaReport};
+use crate::report_tags::{build_tag_report, print_tag_report, TagReport};
+use crate::report_trees::{build_tree_report, print_tree_report, TreeReport};
+use serde::Serialize;
+
+/// The version of the JSON document written by `report_json`. Fields may be added without a
+/// bump, it changes when a field is renamed, removed or changes meaning.
+pub const REPORT_FORMAT_VERSION: u32 = 1;
+
+/// The reports that can be built from a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    Commits,
+    Trees,
+    Blobs,
+    Tags,
+    Deltas,
+    Branches,
+}
+
+impl ReportKind {
+    /// Every report, in the order they are printed.
+    pub const ALL: [ReportKind; 6] = [
+        ReportKind::Commits,
+        ReportKind::Trees,
+        ReportKind::Blobs,
+        ReportKind::Tags,
+        ReportKind::Deltas,
+        ReportKind::Branches,
+    ];
+}
+
+/// An object picked out by a report, such as the largest blob. `size_disk` is the bytes it
+/// takes up in the repo, compressed and possibly as a delta.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ObjectEntry {
+    pub hash: String,
+    pub size_disk: u64,
+    /// Every path the object has been seen at, sorted. The root tree is at the empty path.
+    pub paths: Vec<String>,
+}
+
+impl ObjectEntry {
+    /// The first of `paths` and how many others there are, None if the object was not seen
+    /// at any path.
+    pub fn first_path(&self) -> Option<(&str, usize)> {
+        self.paths
+            .first()
+            .map(|path| (path.as_str(), self.paths.len() - 1))
+    }
+}
+
+/// Every report that was asked for, the rest are `None`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub format_version: u32,
+    pub commits: Option<CommitReport>,
+    pub trees: Option<TreeReport>,
+    pub blobs: Option<BlobReport>,
+    pub tags: Option<TagReport>,
+    pub deltas: Option<DeltaReport>,
+    pub branches: Option<BranchReport>,
+}
+
+/// Build each report in `kinds`. The tag and branch reports need the tags and refs to have
+/// been read.
+pub fn build_report(container: &ObjectContainer, kinds: &[ReportKind]) -> Report {
+    let wants = |kind: ReportKind| kinds.contains(&kind);
+    Report {
+        format_version: REPORT_FORMAT_VERSION,
+        commits: wants(ReportKind::Commits).then(|| build_commit_report(container)),
+        trees: wants(ReportKind::Trees).then(|| build_tree_report(container)),
+        blobs: wants(ReportKind::Blobs).then(|| build_blob_report(container)),
+        tags: wants(ReportKind::Tags).then(|| build_tag_report(container)),
+        deltas: wants(ReportKind::Deltas).then(|| build_delta_report(container)),
+        branches: wants(ReportKind::Branches).then(|| build_branch_report(container)),
+    }
+}
+
+/// Print every report that was built as text.
+pub fn print_report(report: &Report) {
+    if let Some(commits) = &report.commits {
+        print_commit_report(commits);
+    }
+    if let Some(trees) = &report.trees {
+        print_tree_report(trees);
+    }
+    if let Some(blobs) = &report.blobs {
+        print_blob_report(blobs);
+    }
+    if let Some(tags) = &report.tags {
+        print_tag_report(tags);
+    }
+    if let Some(deltas) = &report.deltas {
+        print_delta_report(deltas);
+    }
+    if let Some(branches) = &report.branches {
+        print_branch_report(branches);
+    }
+}
+
+/// The report as a single pretty printed JSON document.
+pub fn report_json(report: &Report) -> String {
+    // Every field is a plain string, number or list of them, which always serializes.
+    serde_json::to_string_pretty(report).expect("reports always serialize to JSON")
+}
 
 pub fn report_all(container: &ObjectContainer) {
-    report_commits(container);
-    report_trees(container);
-    report_blobs(container);
+    print_report(&build_report(container, &ReportKind::ALL));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_source::{InMemorySource, ObjectKind};
+
+    const COMMIT: &str = "1111111111111111111111111111111111111111";
+    const TREE: &str = "2222222222222222222222222222222222222222";
+    const BLOB: &str = "3333333333333333333333333333333333333333";
+
+    #[tokio::test]
+    async fn test_report_json() {
+        let mut source = InMemorySource::new();
+        source.add_object(ObjectKind::Commit, COMMIT, 200, 150);
+        source.add_object(ObjectKind::Tree, TREE, 100, 80);
+        source.add_object(ObjectKind::Blob, BLOB, 1000, 500);
+        source.add_commit_dep(COMMIT, TREE, "");
+        source.add_commit_dep(COMMIT, BLOB, "main.rs");
+        source.add_commit_parents(COMMIT, &[]);
+        source.add_commit_metadata(COMMIT, ("Jane Doe", "jane@example.com"), 10, 20, "Init");
+
+        let container = source.into_container().await;
+
+        let report = build_report(&container, &[ReportKind::Commits, ReportKind::Blobs]);
+        let json: serde_json::Value = serde_json::from_str(&report_json(&report)).unwrap();
+
+        // Reports that were not asked for are still present, as null.
+        assert_eq!(json["format_version"], REPORT_FORMAT_VERSION);
+        assert!(json["trees"].is_null());
+        assert!(json["branches"].is_null());
+
+        let commit = &json["commits"]["largest_contributing_commit"];
+        assert_eq!(commit["hash"], COMMIT);
+        assert_eq!(commit["size_disk"], 80 + 500);
+        assert_eq!(commit["author_email"], "jane@example.com");
+        assert_eq!(commit["commit_time"], 20);
+
+        assert_eq!(json["blobs"]["total_size_disk"], 500);
+        assert_eq!(json["blobs"]["largest_blobs"][0]["hash"], BLOB);
+        assert_eq!(
+            json["blobs"]["largest_blobs"][0]["paths"],
+            serde_json::json!(["main.rs"])
+        );
+    }
 }
@@ -1,65 +1,112 @@
 use crate::object_collection::{ObjectContainer, Properties};
-use crate::utils::display_size;
+use crate::report_all::ObjectEntry;
+use crate::utils::{display_size, keep_largest};
+use serde::Serialize;
 use std::time::Instant;
 
+/// Totals for every blob, and the largest ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlobReport {
+    pub total_blobs: usize,
+    pub total_size_disk: u64,
+    /// The 10 largest blobs on disk, largest first.
+    pub largest_blobs: Vec<ObjectEntry>,
+}
+
 pub fn report_blobs(container: &ObjectContainer) {
-    println!("Building blob report...");
+    print_blob_report(&build_blob_report(container));
+}
+
+pub fn build_blob_report(container: &ObjectContainer) -> BlobReport {
+    eprintln!("Building blob report...");
     let start = Instant::now();
     let mut total_size: u64 = 0;
-    let mut top_ten_size: Vec<(u32, usize)> = Vec::new();
+    // (size, index) of every blob, then only the largest.
+    let mut largest: Vec<(u64, usize)> = Vec::new();
 
     for rw_blob in container.blobs().object_iter() {
         let blob = rw_blob.read().unwrap();
-        total_size += blob.size_disk() as u64;
-
-        // Add until we have 10 items
-        if top_ten_size.len() < 10 {
-            top_ten_size.push((blob.size_disk(), *blob.hash_index()));
-            top_ten_size.sort(); // ascending _by(|a, b| a.cmp(b));
-        } else {
-            // we already have 10 items, does this one fit in the list.
-            if blob.size_disk() > top_ten_size[0].0 {
-                let mut insert_index: usize = 10;
-                // iterate the items until you find which one to insert, pop off the bottom item
-                for (index, value) in top_ten_size.iter().enumerate() {
-                    if blob.size_disk() < value.0 {
-                        insert_index = index - 1;
-                        break;
-                    }
-                }
-
-                // If insert index is 10, this size is larger than the largest collected.
-                if insert_index == 10 {
-                    top_ten_size.insert(9, (blob.size_disk(), *blob.hash_index()));
-                } else {
-                    top_ten_size.insert(insert_index, (blob.size_disk(), *blob.hash_index()));
-                }
-
-                // always remove the smallest item ( top )
-                assert!(top_ten_size.len() == 11);
-                let _ = top_ten_size.remove(0); // dont need the return
-            }
-        }
+        total_size += blob.size_disk();
+        largest.push((blob.size_disk(), *blob.hash_index()));
     }
+    keep_largest(&mut largest, 10);
 
-    // resort to descending
-    top_ten_size.sort_by(|a, b| b.cmp(a));
+    let report = BlobReport {
+        total_blobs: container.blobs().count(),
+        total_size_disk: total_size,
+        largest_blobs: largest
+            .into_iter()
+            .map(|(size_disk, blob_index)| ObjectEntry {
+                hash: container
+                    .blobs()
+                    .lookup_hash_for_index(&blob_index)
+                    .unwrap()
+                    .to_string(),
+                size_disk,
+                paths: container.resolve_paths(
+                    container
+                        .blobs()
+                        .get_by_index(&blob_index)
+                        .read()
+                        .unwrap()
+                        .paths(),
+                ),
+            })
+            .collect(),
+    };
+    eprintln!("Blob report created in: {:?}", start.elapsed());
+    report
+}
 
+pub fn print_blob_report(report: &BlobReport) {
     println!();
     println!("Blob Report");
     println!("-------------------------------------------------------");
-    println!("Total Blobs: {}", container.blobs().count());
-    println!("Total Blobs Size: {}", display_size(total_size));
+    println!("Total Blobs: {}", report.total_blobs);
+    println!("Total Blobs Size: {}", display_size(report.total_size_disk));
     println!("Top 10 Largest Blobs:");
-    for (size, blob_index) in top_ten_size {
+    for blob in &report.largest_blobs {
+        let path = match blob.first_path() {
+            Some((path, 0)) => format!(", Path: {path}"),
+            Some((path, more)) => format!(", Path: {path} (+{more} more)"),
+            None => String::new(),
+        };
         println!(
-            "\tBlob Size: {}, Hash: {}",
-            display_size(size as u64),
-            container
-                .blobs()
-                .lookup_hash_for_index(&blob_index)
-                .unwrap()
-        )
+            "\tBlob Size: {}, Hash: {}{path}",
+            display_size(blob.size_disk),
+            blob.hash
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_processing::process_initial_repo;
+    use crate::object_source::{InMemorySource, ObjectKind};
+
+    #[test]
+    fn test_build_blob_report_largest_blobs() {
+        // More blobs than fit in the report, neither ascending nor descending.
+        let sizes: [u64; 14] = [
+            50, 900, 10, 700, 300, 1200, 5, 800, 60, 1100, 400, 20, 1000, 600,
+        ];
+        let mut source = InMemorySource::new();
+        for (index, size) in sizes.iter().enumerate() {
+            source.add_object(ObjectKind::Blob, &format!("{index:040x}"), size * 2, *size);
+        }
+        let mut container = ObjectContainer::new();
+        process_initial_repo(&source, &mut container).unwrap();
+
+        let report = build_blob_report(&container);
+        assert_eq!(report.total_blobs, sizes.len());
+        assert_eq!(report.total_size_disk, sizes.iter().sum::<u64>());
+
+        let mut expected = sizes.to_vec();
+        expected.sort_by(|a, b| b.cmp(a));
+        expected.truncate(10);
+        let largest: Vec<u64> = report.largest_blobs.iter().map(|b| b.size_disk).collect();
+        assert_eq!(largest, expected);
+        assert_eq!(report.largest_blobs[0].hash, format!("{:040x}", 5));
     }
-    println!("Blob report created in: {:?}", start.elapsed());
 }
@@ -0,0 +1,322 @@
+use crate::object_collection::ObjectContainer;
+use crate::utils::display_size;
+use serde::Serialize;
+use std::{collections::HashSet, time::Instant};
+
+// Marks a commit reached by more than one ref.
+const SHARED: usize = usize::MAX;
+
+/// How much each ref keeps alive that the default branch does not.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BranchReport {
+    pub default_branch: Option<DefaultBranch>,
+    /// Every ref other than `HEAD`, including those with nothing off the default branch.
+    pub total_refs: usize,
+    /// The refs with commits not on the default branch, largest unique size first.
+    pub refs: Vec<RefSize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DefaultBranch {
+    pub name: String,
+    pub hash: String,
+}
+
+/// How much of the repo a single ref keeps alive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RefSize {
+    pub name: String,
+    /// The commit the ref points at.
+    pub hash: String,
+    /// Commits reachable from the ref but not from the default branch.
+    pub commits_not_on_default: usize,
+    /// Disk size of the commits, trees and blobs reachable from the ref but not from the
+    /// default branch.
+    pub not_on_default_size: u64,
+    /// Disk size of the objects reachable from the ref and from nothing else, not the default
+    /// branch nor any other ref. Roughly what deleting the ref would save once it is gc'd.
+    pub unique_size: u64,
+}
+
+pub fn report_branches(container: &ObjectContainer) {
+    print_branch_report(&build_branch_report(container));
+}
+
+pub fn build_branch_report(container: &ObjectContainer) -> BranchReport {
+    eprintln!("Building branch report...");
+    let start = Instant::now();
+
+    let mut sizes = calc_ref_sizes(container);
+    let total_refs = sizes.len();
+    sizes.retain(|size| size.commits_not_on_default > 0);
+    // Largest savings first.
+    sizes.sort_by(|a, b| {
+        b.unique_size
+            .cmp(&a.unique_size)
+            .then(b.not_on_default_size.cmp(&a.not_on_default_size))
+            .then(a.name.cmp(&b.name))
+    });
+
+    let report = BranchReport {
+        default_branch: default_branch(container).map(|(name, commit)| DefaultBranch {
+            name,
+            hash: commit_hash(container, commit),
+        }),
+        total_refs,
+        refs: sizes,
+    };
+    eprintln!("Branch report created in: {:?}", start.elapsed());
+    report
+}
+
+pub fn print_branch_report(report: &BranchReport) {
+    println!();
+    println!("Branch Report");
+    println!("-------------------------------------------------------");
+    match &report.default_branch {
+        Some(branch) => println!("Default Branch: {} ({})", branch.name, branch.hash),
+        None => println!("Default Branch: none"),
+    }
+    println!("Total Refs: {}", report.total_refs);
+    println!(
+        "Refs With Commits Not on the Default Branch: {}",
+        report.refs.len()
+    );
+    for size in &report.refs {
+        println!(
+            "\t{}: Unique Size: {}, Size Not on Default Branch: {}, Commits Not on Default Branch: {}",
+            size.name,
+            display_size(size.unique_size),
+            display_size(size.not_on_default_size),
+            size.commits_not_on_default
+        );
+    }
+}
+
+fn commit_hash(container: &ObjectContainer, commit: usize) -> String {
+    container
+        .commits()
+        .lookup_hash_for_index(&commit)
+        .unwrap()
+        .to_string()
+}
+
+/// The name and commit of the default branch, the branch `HEAD` points at. Falls back to
+/// `main` or `master` when there is no `HEAD`, as in a scoped read.
+pub fn default_branch(container: &ObjectContainer) -> Option<(String, usize)> {
+    let refs = container.refs();
+    let head = refs.iter().find(|(name, _)| name == "HEAD");
+    let Some((_, head_commit)) = head else {
+        return refs
+            .into_iter()
+            .find(|(name, _)| name == "refs/heads/main" || name == "refs/heads/master");
+    };
+
+    // Name the branch rather than HEAD where one points at the same commit.
+    let name = refs
+        .iter()
+        .find(|(name, commit)| commit == head_commit && name.starts_with("refs/heads/"))
+        .map_or("HEAD", |(name, _)| name.as_str());
+    Some((name.to_string(), *head_commit))
+}
+
+/// Work out how much each ref, other than `HEAD`, keeps alive that the default branch does
+/// not. Uses the commits that introduced each tree and blob, an object is reachable from a
+/// ref exactly when one of the commits that introduced it is.
+pub fn calc_ref_sizes(container: &ObjectContainer) -> Vec<RefSize> {
+    let count = container.commits().count();
+    let mut on_default = vec![false; count];
+    if let Some((_, commit)) = default_branch(container) {
+        on_default[commit] = true;
+        for ancestor in container.ancestors(&commit) {
+            on_default[ancestor] = true;
+        }
+    }
+
+    // The commits each ref reaches off the default branch, and which ref reaches each commit.
+    let refs: Vec<(String, usize)> = container
+        .refs()
+        .into_iter()
+        .filter(|(name, _)| name != "HEAD")
+        .collect();
+    let mut owner: Vec<Option<usize>> = vec![None; count];
+    let reached: Vec<Vec<usize>> = refs
+        .iter()
+        .enumerate()
+        .map(|(ref_index, (_, tip))| {
+            let commits = commits_off_default(container, *tip, &on_default);
+            for commit in &commits {
+                owner[*commit] = match owner[*commit] {
+                    None => Some(ref_index),
+                    Some(other) if other == ref_index => Some(ref_index),
+                    Some(_) => Some(SHARED),
+                };
+            }
+            commits
+        })
+        .collect();
+
+    // An object belongs to the ref alone if every commit that introduced it does. A commit no
+    // ref reaches does not keep anything alive.
+    let only_reached_by = |introduced_by: &[usize], ref_index: usize| {
+        introduced_by.iter().all(|commit| {
+            !on_default[*commit] && owner[*commit].is_none_or(|owner| owner == ref_index)
+        })
+    };
+    let off_default = |introduced_by: &[usize]| !introduced_by.iter().any(|c| on_default[*c]);
+
+    let mut sizes = Vec::with_capacity(refs.len());
+    for (ref_index, ((name, tip), commits)) in refs.into_iter().zip(reached).enumerate() {
+        let mut size = RefSize {
+            name,
+            hash: commit_hash(container, tip),
+            commits_not_on_default: commits.len(),
+            not_on_default_size: 0,
+            unique_size: 0,
+        };
+        let mut seen_trees: HashSet<usize> = HashSet::new();
+        let mut seen_blobs: HashSet<usize> = HashSet::new();
+
+        for commit_index in commits {
+            let commit = container
+                .commits()
+                .get_by_index(&commit_index)
+                .read()
+                .unwrap();
+            size.not_on_default_size += commit.size_disk();
+            if owner[commit_index] == Some(ref_index) {
+                size.unique_size += commit.size_disk();
+            }
+
+            for tree_index in commit.tree_deps() {
+                if !seen_trees.insert(*tree_index) {
+                    continue;
+                }
+                let tree = container.trees().get_by_index(tree_index).read().unwrap();
+                if off_default(tree.commits()) {
+                    size.not_on_default_size += tree.size_disk();
+                }
+                if only_reached_by(tree.commits(), ref_index) {
+                    size.unique_size += tree.size_disk();
+                }
+            }
+            for blob_index in commit.blob_deps() {
+                if !seen_blobs.insert(*blob_index) {
+                    continue;
+                }
+                let blob = container.blobs().get_by_index(blob_index).read().unwrap();
+                if off_default(blob.commits()) {
+                    size.not_on_default_size += blob.size_disk();
+                }
+                if only_reached_by(blob.commits(), ref_index) {
+                    size.unique_size += blob.size_disk();
+                }
+            }
+        }
+        sizes.push(size);
+    }
+    sizes
+}
+
+// Every commit reachable from `tip` that is not on the default branch. The walk stops at the
+// default branch, so a ref that was merged long ago only visits its own commits.
+fn commits_off_default(container: &ObjectContainer, tip: usize, on_default: &[bool]) -> Vec<usize> {
+    let mut seen: HashSet<usize> = HashSet::new();
+    let mut stack = vec![tip];
+    let mut found = Vec::new();
+    while let Some(index) = stack.pop() {
+        if on_default[index] || !seen.insert(index) {
+            continue;
+        }
+        found.push(index);
+        let commit = container.commits().get_by_index(&index).read().unwrap();
+        stack.extend(commit.parents());
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_source::{InMemorySource, ObjectKind};
+
+    const ROOT: &str = "1111111111111111111111111111111111111111";
+    const MAIN: &str = "2222222222222222222222222222222222222222";
+    const FEATURE: &str = "3333333333333333333333333333333333333333";
+    const SHARED_TIP: &str = "4444444444444444444444444444444444444444";
+    const ROOT_TREE: &str = "5555555555555555555555555555555555555555";
+    const MAIN_TREE: &str = "6666666666666666666666666666666666666666";
+    const FEATURE_TREE: &str = "7777777777777777777777777777777777777777";
+    const SHARED_TREE: &str = "8888888888888888888888888888888888888888";
+    const ROOT_BLOB: &str = "9999999999999999999999999999999999999999";
+    const FEATURE_BLOB: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const SHARED_BLOB: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    // root <- main (HEAD), root <- feature, root <- shared_tip which both `shared` and
+    // `also-shared` point at.
+    async fn build_container() -> ObjectContainer {
+        let mut source = InMemorySource::new();
+        for (commit, tree) in [
+            (ROOT, ROOT_TREE),
+            (MAIN, MAIN_TREE),
+            (FEATURE, FEATURE_TREE),
+            (SHARED_TIP, SHARED_TREE),
+        ] {
+            source.add_object(ObjectKind::Commit, commit, 200, 100);
+            source.add_object(ObjectKind::Tree, tree, 50, 10);
+            source.add_commit_dep(commit, tree, "");
+        }
+        source.add_object(ObjectKind::Blob, ROOT_BLOB, 5000, 1000);
+        source.add_object(ObjectKind::Blob, FEATURE_BLOB, 5000, 2000);
+        source.add_object(ObjectKind::Blob, SHARED_BLOB, 5000, 3000);
+        source.add_commit_dep(ROOT, ROOT_BLOB, "a.bin");
+        source.add_commit_dep(FEATURE, FEATURE_BLOB, "b.bin");
+        // Also introduced on main, so deleting the feature branch does not free it.
+        source.add_commit_dep(FEATURE, ROOT_BLOB, "c.bin");
+        source.add_commit_dep(SHARED_TIP, SHARED_BLOB, "d.bin");
+
+        source.add_commit_parents(ROOT, &[]);
+        for commit in [MAIN, FEATURE, SHARED_TIP] {
+            source.add_commit_parents(commit, &[ROOT]);
+        }
+        source.add_ref("HEAD", MAIN);
+        source.add_ref("refs/heads/main", MAIN);
+        source.add_ref("refs/heads/feature", FEATURE);
+        source.add_ref("refs/heads/shared", SHARED_TIP);
+        source.add_ref("refs/heads/also-shared", SHARED_TIP);
+
+        let container = source.into_container().await;
+        container
+    }
+
+    #[tokio::test]
+    async fn test_calc_ref_sizes() {
+        let container = build_container().await;
+        let main = *container.commits().get_index_by_hex(MAIN).unwrap();
+        assert_eq!(
+            default_branch(&container),
+            Some(("refs/heads/main".to_string(), main))
+        );
+
+        let sizes = calc_ref_sizes(&container);
+        let find = |name: &str| sizes.iter().find(|size| size.name == name).unwrap();
+        assert_eq!(sizes.len(), 4);
+
+        let main = find("refs/heads/main");
+        assert_eq!(main.commits_not_on_default, 0);
+        assert_eq!(main.not_on_default_size, 0);
+
+        // The commit, its tree and the new blob, the blob also on main is not counted.
+        let feature = find("refs/heads/feature");
+        assert_eq!(feature.commits_not_on_default, 1);
+        assert_eq!(feature.not_on_default_size, 100 + 10 + 2000);
+        assert_eq!(feature.unique_size, 100 + 10 + 2000);
+
+        // Both refs keep the same commit alive, so neither can free it alone.
+        for name in ["refs/heads/shared", "refs/heads/also-shared"] {
+            let shared = find(name);
+            assert_eq!(shared.not_on_default_size, 100 + 10 + 3000);
+            assert_eq!(shared.unique_size, 0);
+        }
+    }
+}
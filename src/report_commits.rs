@@ -1,80 +1,162 @@
 use crate::commit::Commit;
 use crate::object_collection::{ObjectContainer, Properties};
-use crate::utils::display_size;
+use crate::utils::{display_size, display_time};
+use serde::Serialize;
 use std::{sync::RwLockReadGuard, time::Instant};
 
+/// Totals for every commit object, and the commits that stand out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitReport {
+    pub total_commits: usize,
+    /// Disk size of the commit objects alone, not what they reference.
+    pub total_size_disk: u64,
+    /// The largest commit object.
+    pub largest_commit: Option<CommitEntry>,
+    /// The commit whose new trees, blobs and tags take up the most space.
+    pub largest_contributing_commit: Option<CommitEntry>,
+}
+
+/// A commit picked out by the report. For the largest contributing commit `size_disk` is the
+/// size of everything it introduced rather than the commit object. The trees and blobs a
+/// commit introduced are the ones that differ from its first parent, which includes blobs it
+/// moved, copied or reverted to even when an older commit added them first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitEntry {
+    pub hash: String,
+    pub size_disk: u64,
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    /// Seconds since the unix epoch.
+    pub author_time: Option<i64>,
+    /// Seconds since the unix epoch.
+    pub commit_time: Option<i64>,
+    pub subject: Option<String>,
+}
+
 pub fn report_commits(container: &ObjectContainer) {
-    println!("Building tree report...");
+    print_commit_report(&build_commit_report(container));
+}
+
+pub fn build_commit_report(container: &ObjectContainer) -> CommitReport {
+    eprintln!("Building commit report...");
     let start = Instant::now();
 
     let mut total_size: u64 = 0;
-    let mut largest_commit_size: u32 = 0;
-    let mut largest_commmit_index: usize = 0;
-    let mut largest_contributing_size: u64 = 0;
-    let mut largest_contributing_commit_index: usize = 0;
+    let mut largest_commit: Option<(u64, usize)> = None;
+    let mut largest_contributing: Option<(u64, usize)> = None;
     for rw_commit in container.commits().object_iter() {
         let commit = rw_commit.read().unwrap();
-        total_size += commit.size_disk() as u64;
-        if largest_commit_size < commit.size_disk() {
-            largest_commit_size = commit.size_disk();
-            largest_commmit_index = *commit.hash_index();
+        total_size += commit.size_disk();
+        if largest_commit.is_none_or(|(size, _)| size < commit.size_disk()) {
+            largest_commit = Some((commit.size_disk(), *commit.hash_index()));
         }
 
         // maybe store back in commit?
         let contributing = calc_commit_size(&commit, container);
-        if largest_contributing_size < contributing {
-            largest_contributing_size = contributing;
-            largest_contributing_commit_index = *commit.hash_index();
+        if largest_contributing.is_none_or(|(size, _)| size < contributing) {
+            largest_contributing = Some((contributing, *commit.hash_index()));
         }
     }
 
+    let report = CommitReport {
+        total_commits: container.commits().count(),
+        total_size_disk: total_size,
+        largest_commit: largest_commit.map(|(size, index)| commit_entry(size, index, container)),
+        largest_contributing_commit: largest_contributing
+            .map(|(size, index)| commit_entry(size, index, container)),
+    };
+    eprintln!("Commit report created in: {:?}", start.elapsed());
+    report
+}
+
+pub fn print_commit_report(report: &CommitReport) {
     println!();
     println!("Commit Report");
     println!("-------------------------------------------------------");
-    println!("Total Commits: {}", container.commits().count());
-    println!("Total Commits Size: {}", display_size(total_size));
+    println!("Total Commits: {}", report.total_commits);
     println!(
-        "Largest Commit Object Size: {}",
-        display_size(largest_commit_size as u64)
+        "Total Commits Size: {}",
+        display_size(report.total_size_disk)
     );
-    println!(
-        "Largest Commit Object Id: {}",
-        container
+    if let Some(commit) = &report.largest_commit {
+        println!(
+            "Largest Commit Object Size: {}",
+            display_size(commit.size_disk)
+        );
+        println!("Largest Commit Object Id: {}", commit.hash);
+    }
+    if let Some(commit) = &report.largest_contributing_commit {
+        println!(
+            "Largest Contributing Commit Size: {}",
+            display_size(commit.size_disk)
+        );
+        println!("Largest Contributing Commit Object Id: {}", commit.hash);
+        print_commit_metadata("Largest Contributing Commit", commit);
+    }
+    println!("\n");
+}
+
+// Look up the hash and who made the commit, when and its subject, if known.
+fn commit_entry(size_disk: u64, commit_index: usize, container: &ObjectContainer) -> CommitEntry {
+    let commit = container
+        .commits()
+        .get_by_index(&commit_index)
+        .read()
+        .unwrap();
+    let metadata = commit.metadata();
+    let resolve = |id: u32| container.resolve_string(id).map(|s| s.to_string());
+
+    CommitEntry {
+        hash: container
             .commits()
-            .lookup_hash_for_index(&largest_commmit_index)
+            .lookup_hash_for_index(&commit_index)
             .unwrap()
-    );
+            .to_string(),
+        size_disk,
+        author_name: metadata.and_then(|m| resolve(m.author_name)),
+        author_email: metadata.and_then(|m| resolve(m.author_email)),
+        author_time: metadata.map(|m| m.author_time),
+        commit_time: metadata.map(|m| m.commit_time),
+        subject: metadata.and_then(|m| resolve(m.subject)),
+    }
+}
+
+// Print who made the commit, when and its subject, if known.
+fn print_commit_metadata(label: &str, commit: &CommitEntry) {
+    let (Some(author_time), Some(commit_time)) = (commit.author_time, commit.commit_time) else {
+        return;
+    };
+
     println!(
-        "Largest Contributing Commit Size: {}",
-        display_size(largest_contributing_size)
+        "{label} Author: {} <{}>",
+        commit.author_name.as_deref().unwrap_or_default(),
+        commit.author_email.as_deref().unwrap_or_default()
     );
+    println!("{label} Date: {}", display_time(author_time));
+    println!("{label} Committed: {}", display_time(commit_time));
     println!(
-        "Largest Contributing Commit Object Id: {}\n\n",
-        container
-            .commits()
-            .lookup_hash_for_index(&largest_contributing_commit_index)
-            .unwrap()
+        "{label} Subject: {}",
+        commit.subject.as_deref().unwrap_or_default()
     );
-    println!("Commit report created in: {:?}", start.elapsed());
 }
 
 pub fn calc_commit_size(commit: &RwLockReadGuard<'_, Commit>, container: &ObjectContainer) -> u64 {
     let mut total_blob_size: u64 = 0;
     for blob_index in commit.blob_deps() {
         let blob = container.blobs().get_by_index(blob_index).read().unwrap();
-        total_blob_size += blob.size_disk() as u64;
+        total_blob_size += blob.size_disk();
     }
 
     let mut total_tree_size: u64 = 0;
     for tree_index in commit.tree_deps() {
         let tree = container.trees().get_by_index(tree_index).read().unwrap();
-        total_tree_size += tree.size_disk() as u64;
+        total_tree_size += tree.size_disk();
     }
 
     let mut total_tag_size: u64 = 0;
     for tag_index in commit.tag_deps() {
         let tag = container.tags().get_by_index(tag_index).read().unwrap();
-        total_tag_size += tag.size_disk() as u64;
+        total_tag_size += tag.size_disk();
     }
 
     total_blob_size + total_tree_size + total_tag_size
@@ -0,0 +1,214 @@
+use crate::object_collection::{BasicObjectContainer, Delta, ObjectContainer, Properties};
+use crate::utils::{display_size, keep_largest};
+use serde::Serialize;
+use std::time::Instant;
+
+/// How objects are stored, whole or as deltas against another object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeltaReport {
+    pub objects_whole: usize,
+    pub objects_whole_size_disk: u64,
+    pub objects_delta: usize,
+    pub objects_delta_size_disk: u64,
+    pub blobs_whole: usize,
+    pub blobs_whole_size_disk: u64,
+    pub blobs_delta: usize,
+    pub blobs_delta_size_disk: u64,
+    /// The 10 largest blobs by their full size, largest first.
+    pub largest_blobs: Vec<BlobStorage>,
+    /// The 10 deepest delta chains, deepest first.
+    pub deepest_chains: Vec<DeltaChain>,
+}
+
+/// A blob along with how it is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlobStorage {
+    pub hash: String,
+    pub size: u64,
+    pub size_disk: u64,
+    /// How many deltas deep the blob is, 0 when stored whole.
+    pub delta_depth: u32,
+    /// The object the blob is a delta against, if it is a delta and the base is known.
+    pub delta_base: Option<String>,
+}
+
+/// An object at the end of a delta chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeltaChain {
+    pub depth: u32,
+    /// `commit`, `tree`, `blob` or `tag`.
+    pub kind: String,
+    pub hash: String,
+}
+
+// Running totals of how the objects of one type are stored.
+#[derive(Default)]
+struct DeltaTotals {
+    whole_count: usize,
+    whole_size: u64,
+    delta_count: usize,
+    delta_size: u64,
+}
+
+impl DeltaTotals {
+    fn add(&mut self, is_delta: bool, size_disk: u64) {
+        if is_delta {
+            self.delta_count += 1;
+            self.delta_size += size_disk;
+        } else {
+            self.whole_count += 1;
+            self.whole_size += size_disk;
+        }
+    }
+}
+
+pub fn report_deltas(container: &ObjectContainer) {
+    print_delta_report(&build_delta_report(container));
+}
+
+pub fn build_delta_report(container: &ObjectContainer) -> DeltaReport {
+    eprintln!("Building delta report...");
+    let start = Instant::now();
+
+    let mut totals = DeltaTotals::default();
+    // (depth, type, index)
+    let mut deepest: Vec<(u32, &str, usize)> = Vec::new();
+
+    for rw_commit in container.commits().object_iter() {
+        let commit = rw_commit.read().unwrap();
+        totals.add(commit.is_delta(), commit.size_disk());
+        deepest.push((commit.delta_depth(), "commit", *commit.hash_index()));
+    }
+    for rw_tree in container.trees().object_iter() {
+        let tree = rw_tree.read().unwrap();
+        totals.add(tree.is_delta(), tree.size_disk());
+        deepest.push((tree.delta_depth(), "tree", *tree.hash_index()));
+    }
+    for rw_tag in container.tags().object_iter() {
+        let tag = rw_tag.read().unwrap();
+        totals.add(tag.is_delta(), tag.size_disk());
+        deepest.push((tag.delta_depth(), "tag", *tag.hash_index()));
+    }
+
+    let mut blob_totals = DeltaTotals::default();
+    // (size, index) of every blob, then only the largest.
+    let mut largest_blobs: Vec<(u64, usize)> = Vec::new();
+    for rw_blob in container.blobs().object_iter() {
+        let blob = rw_blob.read().unwrap();
+        blob_totals.add(blob.is_delta(), blob.size_disk());
+        deepest.push((blob.delta_depth(), "blob", *blob.hash_index()));
+        largest_blobs.push((blob.size(), *blob.hash_index()));
+    }
+    totals.whole_count += blob_totals.whole_count;
+    totals.whole_size += blob_totals.whole_size;
+    totals.delta_count += blob_totals.delta_count;
+    totals.delta_size += blob_totals.delta_size;
+
+    keep_largest(&mut largest_blobs, 10);
+    deepest.retain(|(depth, _, _)| *depth > 0);
+    keep_largest(&mut deepest, 10);
+
+    let report = DeltaReport {
+        objects_whole: totals.whole_count,
+        objects_whole_size_disk: totals.whole_size,
+        objects_delta: totals.delta_count,
+        objects_delta_size_disk: totals.delta_size,
+        blobs_whole: blob_totals.whole_count,
+        blobs_whole_size_disk: blob_totals.whole_size,
+        blobs_delta: blob_totals.delta_count,
+        blobs_delta_size_disk: blob_totals.delta_size,
+        largest_blobs: largest_blobs
+            .into_iter()
+            .map(|(size, blob_index)| {
+                let blob = container.blobs().get_by_index(&blob_index).read().unwrap();
+                BlobStorage {
+                    hash: hash_for_index(container.blobs(), blob_index),
+                    size,
+                    size_disk: blob.size_disk(),
+                    delta_depth: blob.delta_depth(),
+                    delta_base: blob
+                        .delta_base()
+                        .and_then(|index| container.blobs().lookup_hash_for_index(&index))
+                        .map(ToString::to_string),
+                }
+            })
+            .collect(),
+        deepest_chains: deepest
+            .into_iter()
+            .map(|(depth, kind, index)| DeltaChain {
+                depth,
+                kind: kind.to_string(),
+                hash: match kind {
+                    "commit" => hash_for_index(container.commits(), index),
+                    "tree" => hash_for_index(container.trees(), index),
+                    "tag" => hash_for_index(container.tags(), index),
+                    _ => hash_for_index(container.blobs(), index),
+                },
+            })
+            .collect(),
+    };
+    eprintln!("Delta report created in: {:?}", start.elapsed());
+    report
+}
+
+pub fn print_delta_report(report: &DeltaReport) {
+    println!();
+    println!("Delta Report");
+    println!("-------------------------------------------------------");
+    println!("Objects Stored Whole: {}", report.objects_whole);
+    println!("Objects Stored as Deltas: {}", report.objects_delta);
+    println!(
+        "Total Size of Non-Deltified Objects: {}",
+        display_size(report.objects_whole_size_disk)
+    );
+    println!(
+        "Total Size of Deltas: {}",
+        display_size(report.objects_delta_size_disk)
+    );
+    println!("Blobs Stored Whole: {}", report.blobs_whole);
+    println!(
+        "Total Size of Non-Deltified Blobs: {}",
+        display_size(report.blobs_whole_size_disk)
+    );
+    println!("Blobs Stored as Deltas: {}", report.blobs_delta);
+    println!(
+        "Total Size of Blob Deltas: {}",
+        display_size(report.blobs_delta_size_disk)
+    );
+
+    println!("Top 10 Largest Blobs:");
+    for blob in &report.largest_blobs {
+        println!(
+            "\tBlob Size: {}, Size on Disk: {}, Stored: {}, Hash: {}",
+            display_size(blob.size),
+            display_size(blob.size_disk),
+            describe_storage(blob),
+            blob.hash
+        );
+    }
+
+    println!("Top 10 Deepest Delta Chains:");
+    for chain in &report.deepest_chains {
+        println!(
+            "\tDepth: {}, Type: {}, Hash: {}",
+            chain.depth, chain.kind, chain.hash
+        );
+    }
+}
+
+fn hash_for_index<T>(objects: &BasicObjectContainer<T>, index: usize) -> String
+where
+    T: Properties + Delta,
+{
+    objects.lookup_hash_for_index(&index).unwrap().to_string()
+}
+
+// Whole, or a delta along with its chain depth and base.
+fn describe_storage(blob: &BlobStorage) -> String {
+    if blob.delta_depth == 0 {
+        return "Whole".to_string();
+    }
+
+    let base = blob.delta_base.as_deref().unwrap_or("unknown");
+    format!("Delta (Depth: {}, Base: {base})", blob.delta_depth)
+}
@@ -0,0 +1,511 @@
+use crate::report_all::Report;
+use crate::report_commits::CommitEntry;
+use crate::utils::{display_size, display_time};
+
+// Kept inline so the report is a single file that can be attached to a CI run and opened
+// anywhere, with nothing to fetch.
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2em auto; max-width: 70em; color: #222; }
+h1 { border-bottom: 2px solid #444; }
+section { margin-bottom: 2.5em; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 0.2em 1.5em; }
+dt { font-weight: 600; }
+dd { margin: 0; }
+table { border-collapse: collapse; margin: 1em 0; width: 100%; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+th { background: #eee; cursor: pointer; user-select: none; }
+th.asc::after { content: ' \\25B2'; }
+th.desc::after { content: ' \\25BC'; }
+code { font-size: 0.9em; }
+svg text { font-size: 12px; }
+svg rect { fill: #4a78b5; }
+";
+
+// Clicking a header sorts the table by that column, numeric cells sort on their raw value.
+const SCRIPT: &str = "
+document.querySelectorAll('table.sortable th').forEach(function (th) {
+  th.addEventListener('click', function () {
+    var table = th.closest('table');
+    var column = Array.prototype.indexOf.call(th.parentNode.children, th);
+    var ascending = !th.classList.contains('asc');
+    table.querySelectorAll('th').forEach(function (h) { h.classList.remove('asc', 'desc'); });
+    th.classList.add(ascending ? 'asc' : 'desc');
+    var body = table.tBodies[0];
+    var rows = Array.prototype.slice.call(body.rows);
+    var key = function (row) {
+      var cell = row.cells[column];
+      var value = cell.getAttribute('data-value');
+      return value === null ? cell.textContent : parseFloat(value);
+    };
+    rows.sort(function (a, b) {
+      var x = key(a), y = key(b);
+      var order = x < y ? -1 : x > y ? 1 : 0;
+      return ascending ? order : -order;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+";
+
+// A table cell, numbers are right aligned and sort by value rather than by their text.
+enum Cell {
+    Text(String),
+    Code(String),
+    Size(u64),
+    Number(u64),
+}
+
+/// Render every report that was built as a single self contained HTML page, with sortable
+/// tables and bar charts of the largest sizes. The styles and script are embedded, so the
+/// page works offline.
+pub fn report_html(report: &Report) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>Repository Size Report</title>\n");
+    out.push_str(&format!("<style>{STYLE}</style>\n</head>\n<body>\n"));
+    out.push_str("<h1>Repository Size Report</h1>\n");
+
+    // How the space splits between the object types, for whichever reports were built.
+    let totals: Vec<(String, u64)> = [
+        report
+            .commits
+            .as_ref()
+            .map(|r| ("Commits".to_string(), r.total_size_disk)),
+        report
+            .trees
+            .as_ref()
+            .map(|r| ("Trees".to_string(), r.total_size_disk)),
+        report
+            .blobs
+            .as_ref()
+            .map(|r| ("Blobs".to_string(), r.total_size_disk)),
+        report
+            .tags
+            .as_ref()
+            .map(|r| ("Tags".to_string(), r.total_size_disk)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if totals.len() > 1 {
+        out.push_str("<section>\n<h2>Size by Object Type</h2>\n");
+        bar_chart(&mut out, &totals);
+        out.push_str("</section>\n");
+    }
+
+    if let Some(commits) = &report.commits {
+        out.push_str("<section>\n<h2>Commits</h2>\n");
+        facts(
+            &mut out,
+            &[
+                ("Total Commits", commits.total_commits.to_string()),
+                ("Total Commits Size", display_size(commits.total_size_disk)),
+            ],
+        );
+        let rows: Vec<Vec<Cell>> = [
+            ("Largest Commit Object", &commits.largest_commit),
+            (
+                "Largest Contributing Commit",
+                &commits.largest_contributing_commit,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(label, commit)| commit.as_ref().map(|c| commit_row(label, c)))
+        .collect();
+        table(
+            &mut out,
+            &["", "Size", "Hash", "Author", "Date", "Subject"],
+            rows,
+        );
+        out.push_str("</section>\n");
+    }
+
+    if let Some(trees) = &report.trees {
+        out.push_str("<section>\n<h2>Trees</h2>\n");
+        let mut tree_facts = vec![
+            ("Total Trees", trees.total_trees.to_string()),
+            ("Total Trees Size", display_size(trees.total_size_disk)),
+        ];
+        if let Some(tree) = &trees.largest_tree {
+            tree_facts.push(("Largest Tree Object Size", display_size(tree.size_disk)));
+            tree_facts.push(("Largest Tree Object Id", tree.hash.clone()));
+        }
+        if let Some(path) = &trees.most_trees_at_path {
+            tree_facts.push(("Most Trees at Path", path.path.clone()));
+            tree_facts.push(("Count Most Trees at Path", path.count.to_string()));
+            tree_facts.push((
+                "Most Trees at Path Total Size",
+                display_size(path.total_size_disk),
+            ));
+        }
+        facts(&mut out, &tree_facts);
+        out.push_str("</section>\n");
+    }
+
+    if let Some(blobs) = &report.blobs {
+        out.push_str("<section>\n<h2>Blobs</h2>\n");
+        facts(
+            &mut out,
+            &[
+                ("Total Blobs", blobs.total_blobs.to_string()),
+                ("Total Blobs Size", display_size(blobs.total_size_disk)),
+            ],
+        );
+        out.push_str("<h3>Largest Blobs</h3>\n");
+        let bars: Vec<(String, u64)> = blobs
+            .largest_blobs
+            .iter()
+            .map(|blob| (short_hash(&blob.hash), blob.size_disk))
+            .collect();
+        bar_chart(&mut out, &bars);
+        let rows = blobs
+            .largest_blobs
+            .iter()
+            .map(|blob| {
+                let path = match blob.first_path() {
+                    Some((path, 0)) => path.to_string(),
+                    Some((path, more)) => format!("{path} (+{more} more)"),
+                    None => String::new(),
+                };
+                vec![
+                    Cell::Size(blob.size_disk),
+                    Cell::Code(path),
+                    Cell::Code(blob.hash.clone()),
+                ]
+            })
+            .collect();
+        table(&mut out, &["Size on Disk", "Path", "Hash"], rows);
+        out.push_str("</section>\n");
+    }
+
+    if let Some(tags) = &report.tags {
+        out.push_str("<section>\n<h2>Tags</h2>\n");
+        facts(
+            &mut out,
+            &[
+                ("Total Annotated Tags", tags.total_tags.to_string()),
+                (
+                    "Total Annotated Tags Size",
+                    display_size(tags.total_size_disk),
+                ),
+                ("Total Lightweight Tags", tags.lightweight_tags.to_string()),
+            ],
+        );
+        let rows = tags
+            .largest_tags
+            .iter()
+            .map(|tag| {
+                vec![
+                    Cell::Text(tag.name.clone()),
+                    Cell::Size(tag.size_disk),
+                    Cell::Code(tag.hash.clone()),
+                    Cell::Code(tag.commit.clone().unwrap_or_default()),
+                ]
+            })
+            .collect();
+        table(&mut out, &["Name", "Size on Disk", "Hash", "Commit"], rows);
+        out.push_str("</section>\n");
+    }
+
+    if let Some(deltas) = &report.deltas {
+        out.push_str("<section>\n<h2>Deltas</h2>\n");
+        facts(
+            &mut out,
+            &[
+                ("Objects Stored Whole", deltas.objects_whole.to_string()),
+                ("Objects Stored as Deltas", deltas.objects_delta.to_string()),
+                (
+                    "Total Size of Non-Deltified Objects",
+                    display_size(deltas.objects_whole_size_disk),
+                ),
+                (
+                    "Total Size of Deltas",
+                    display_size(deltas.objects_delta_size_disk),
+                ),
+                ("Blobs Stored Whole", deltas.blobs_whole.to_string()),
+                (
+                    "Total Size of Non-Deltified Blobs",
+                    display_size(deltas.blobs_whole_size_disk),
+                ),
+                ("Blobs Stored as Deltas", deltas.blobs_delta.to_string()),
+                (
+                    "Total Size of Blob Deltas",
+                    display_size(deltas.blobs_delta_size_disk),
+                ),
+            ],
+        );
+        out.push_str("<h3>Largest Blobs</h3>\n");
+        let rows = deltas
+            .largest_blobs
+            .iter()
+            .map(|blob| {
+                vec![
+                    Cell::Size(blob.size),
+                    Cell::Size(blob.size_disk),
+                    Cell::Number(u64::from(blob.delta_depth)),
+                    Cell::Code(blob.delta_base.clone().unwrap_or_default()),
+                    Cell::Code(blob.hash.clone()),
+                ]
+            })
+            .collect();
+        table(
+            &mut out,
+            &["Size", "Size on Disk", "Delta Depth", "Delta Base", "Hash"],
+            rows,
+        );
+        out.push_str("<h3>Deepest Delta Chains</h3>\n");
+        let rows = deltas
+            .deepest_chains
+            .iter()
+            .map(|chain| {
+                vec![
+                    Cell::Number(u64::from(chain.depth)),
+                    Cell::Text(chain.kind.clone()),
+                    Cell::Code(chain.hash.clone()),
+                ]
+            })
+            .collect();
+        table(&mut out, &["Depth", "Type", "Hash"], rows);
+        out.push_str("</section>\n");
+    }
+
+    if let Some(branches) = &report.branches {
+        out.push_str("<section>\n<h2>Branches</h2>\n");
+        let default = branches
+            .default_branch
+            .as_ref()
+            .map_or("none".to_string(), |b| format!("{} ({})", b.name, b.hash));
+        facts(
+            &mut out,
+            &[
+                ("Default Branch", default),
+                ("Total Refs", branches.total_refs.to_string()),
+                (
+                    "Refs With Commits Not on the Default Branch",
+                    branches.refs.len().to_string(),
+                ),
+            ],
+        );
+        let bars: Vec<(String, u64)> = branches
+            .refs
+            .iter()
+            .filter(|r| r.unique_size > 0)
+            .take(10)
+            .map(|r| (r.name.clone(), r.unique_size))
+            .collect();
+        if !bars.is_empty() {
+            out.push_str("<h3>Largest Unique Sizes</h3>\n");
+            bar_chart(&mut out, &bars);
+        }
+        let rows = branches
+            .refs
+            .iter()
+            .map(|r| {
+                vec![
+                    Cell::Text(r.name.clone()),
+                    Cell::Size(r.unique_size),
+                    Cell::Size(r.not_on_default_size),
+                    Cell::Number(r.commits_not_on_default as u64),
+                    Cell::Code(r.hash.clone()),
+                ]
+            })
+            .collect();
+        table(
+            &mut out,
+            &[
+                "Ref",
+                "Unique Size",
+                "Size Not on Default Branch",
+                "Commits Not on Default Branch",
+                "Commit",
+            ],
+            rows,
+        );
+        out.push_str("</section>\n");
+    }
+
+    out.push_str(&format!("<script>{SCRIPT}</script>\n</body>\n</html>\n"));
+    out
+}
+
+fn commit_row(label: &str, commit: &CommitEntry) -> Vec<Cell> {
+    let author = match (&commit.author_name, &commit.author_email) {
+        (Some(name), Some(email)) => format!("{name} <{email}>"),
+        _ => String::new(),
+    };
+    vec![
+        Cell::Text(label.to_string()),
+        Cell::Size(commit.size_disk),
+        Cell::Code(commit.hash.clone()),
+        Cell::Text(author),
+        Cell::Text(commit.author_time.map(display_time).unwrap_or_default()),
+        Cell::Text(commit.subject.clone().unwrap_or_default()),
+    ]
+}
+
+// A list of labelled values.
+fn facts(out: &mut String, facts: &[(&str, String)]) {
+    out.push_str("<dl>\n");
+    for (label, value) in facts {
+        out.push_str(&format!(
+            "<dt>{}</dt><dd>{}</dd>\n",
+            escape(label),
+            escape(value)
+        ));
+    }
+    out.push_str("</dl>\n");
+}
+
+fn table(out: &mut String, headers: &[&str], rows: Vec<Vec<Cell>>) {
+    if rows.is_empty() {
+        return;
+    }
+
+    out.push_str("<table class=\"sortable\">\n<thead><tr>");
+    for header in headers {
+        out.push_str(&format!("<th>{}</th>", escape(header)));
+    }
+    out.push_str("</tr></thead>\n<tbody>\n");
+    for row in rows {
+        out.push_str("<tr>");
+        for cell in row {
+            let html = match cell {
+                Cell::Text(text) => format!("<td>{}</td>", escape(&text)),
+                Cell::Code(text) => format!("<td><code>{}</code></td>", escape(&text)),
+                Cell::Size(size) => format!(
+                    "<td class=\"number\" data-value=\"{size}\">{}</td>",
+                    display_size(size)
+                ),
+                Cell::Number(value) => {
+                    format!("<td class=\"number\" data-value=\"{value}\">{value}</td>")
+                }
+            };
+            out.push_str(&html);
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</tbody>\n</table>\n");
+}
+
+// A horizontal bar chart as inline svg, each bar scaled against the largest.
+fn bar_chart(out: &mut String, bars: &[(String, u64)]) {
+    const LABEL_WIDTH: u64 = 260;
+    const BAR_WIDTH: u64 = 440;
+    const ROW_HEIGHT: u64 = 22;
+
+    let Some(max) = bars
+        .iter()
+        .map(|(_, size)| *size)
+        .max()
+        .filter(|max| *max > 0)
+    else {
+        return;
+    };
+
+    let height = ROW_HEIGHT * bars.len() as u64;
+    out.push_str(&format!(
+        "<svg width=\"{}\" height=\"{height}\" role=\"img\">\n",
+        LABEL_WIDTH + BAR_WIDTH + 100
+    ));
+    for (row, (label, size)) in bars.iter().enumerate() {
+        let y = ROW_HEIGHT * row as u64;
+        // Always draw a sliver so small but non zero sizes are still visible.
+        let width = (BAR_WIDTH * size / max).max(1);
+        out.push_str(&format!(
+            "<text x=\"0\" y=\"{}\">{}</text>",
+            y + 15,
+            escape(label)
+        ));
+        out.push_str(&format!(
+            "<rect x=\"{LABEL_WIDTH}\" y=\"{}\" width=\"{width}\" height=\"{}\"><title>{}</title></rect>",
+            y + 3,
+            ROW_HEIGHT - 6,
+            display_size(*size)
+        ));
+        out.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\">{}</text>\n",
+            LABEL_WIDTH + width + 6,
+            y + 15,
+            display_size(*size)
+        ));
+    }
+    out.push_str("</svg>\n");
+}
+
+fn short_hash(hash: &str) -> String {
+    hash.chars().take(12).collect()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_all::{ObjectEntry, REPORT_FORMAT_VERSION};
+    use crate::report_blobs::BlobReport;
+    use crate::report_tags::{TagEntry, TagReport};
+
+    #[test]
+    fn test_report_html() {
+        let report = Report {
+            format_version: REPORT_FORMAT_VERSION,
+            commits: None,
+            trees: None,
+            blobs: Some(BlobReport {
+                total_blobs: 2,
+                total_size_disk: 3072,
+                largest_blobs: vec![
+                    ObjectEntry {
+                        hash: "a".repeat(40),
+                        size_disk: 2048,
+                        paths: vec!["assets/logo.png".to_string(), "logo.png".to_string()],
+                    },
+                    ObjectEntry {
+                        hash: "b".repeat(40),
+                        size_disk: 1024,
+                        paths: Vec::new(),
+                    },
+                ],
+            }),
+            tags: Some(TagReport {
+                total_tags: 1,
+                total_size_disk: 140,
+                lightweight_tags: 0,
+                largest_tags: vec![TagEntry {
+                    name: "refs/tags/<script>".to_string(),
+                    hash: "c".repeat(40),
+                    size_disk: 140,
+                    commit: None,
+                }],
+            }),
+            deltas: None,
+            branches: None,
+        };
+
+        let html = report_html(&report);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h2>Size by Object Type</h2>"));
+        assert!(html.contains("<h2>Blobs</h2>"));
+        assert!(html.contains("<td class=\"number\" data-value=\"2048\">2.00 KB</td>"));
+        assert!(html.contains("<td><code>assets/logo.png (+1 more)</code></td>"));
+        assert!(html.contains("<h2>Tags</h2>"));
+        assert!(!html.contains("<h2>Commits</h2>"));
+        // Names from the repo are escaped, the only script is the sorting one.
+        assert!(html.contains("refs/tags/&lt;script&gt;"));
+        assert_eq!(html.matches("<script>").count(), 1);
+    }
+}
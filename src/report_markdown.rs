@@ -0,0 +1,386 @@
+use crate::report_all::Report;
+use crate::report_commits::CommitEntry;
+use crate::utils::{display_size, display_time};
+
+/// The most bytes `report_markdown` writes. Review comments are capped at 65536 characters
+/// on GitHub and 1MB on GitLab, and a character is never less than a byte, so this leaves
+/// room for text added around it.
+pub const MAX_MARKDOWN_LEN: usize = 60_000;
+
+// Rows in any one table, anything past this is summed up in a line below it.
+const MAX_ROWS: usize = 10;
+// Longer names, paths and subjects are cut short so a table stays readable.
+const MAX_CELL_CHARS: usize = 80;
+
+/// Render every report that was built as Markdown, headings and tables that can be posted as
+/// a review comment as they are. Tables hold at most 10 rows and long values are shortened,
+/// so the whole document stays under `MAX_MARKDOWN_LEN`.
+pub fn report_markdown(report: &Report) -> String {
+    let mut out = String::from("## Repository Size Report\n");
+
+    if let Some(commits) = &report.commits {
+        out.push_str("\n### Commits\n\n");
+        facts(
+            &mut out,
+            &[
+                ("Total commits", commits.total_commits.to_string()),
+                ("Total size", display_size(commits.total_size_disk)),
+            ],
+        );
+        let rows: Vec<Vec<String>> = [
+            ("Largest commit object", &commits.largest_commit),
+            (
+                "Largest contributing commit",
+                &commits.largest_contributing_commit,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(label, commit)| commit.as_ref().map(|c| commit_row(label, c)))
+        .collect();
+        table(
+            &mut out,
+            &["", "Size", "Commit", "Author", "Date", "Subject"],
+            rows,
+        );
+    }
+
+    if let Some(trees) = &report.trees {
+        out.push_str("\n### Trees\n\n");
+        let mut tree_facts = vec![
+            ("Total trees", trees.total_trees.to_string()),
+            ("Total size", display_size(trees.total_size_disk)),
+        ];
+        if let Some(tree) = &trees.largest_tree {
+            tree_facts.push((
+                "Largest tree",
+                format!(
+                    "{} `{}`",
+                    display_size(tree.size_disk),
+                    short_hash(&tree.hash)
+                ),
+            ));
+        }
+        facts(&mut out, &tree_facts);
+        if let Some(path) = &trees.most_trees_at_path {
+            table(
+                &mut out,
+                &["Path with the most trees", "Trees", "Total size"],
+                vec![vec![
+                    // the trees at the root of the repo have no path.
+                    if path.path.is_empty() {
+                        "_(root)_".to_string()
+                    } else {
+                        code(&path.path)
+                    },
+                    path.count.to_string(),
+                    display_size(path.total_size_disk),
+                ]],
+            );
+        }
+    }
+
+    if let Some(blobs) = &report.blobs {
+        out.push_str("\n### Blobs\n\n");
+        facts(
+            &mut out,
+            &[
+                ("Total blobs", blobs.total_blobs.to_string()),
+                ("Total size", display_size(blobs.total_size_disk)),
+            ],
+        );
+        let rows = blobs
+            .largest_blobs
+            .iter()
+            .map(|blob| {
+                vec![
+                    display_size(blob.size_disk),
+                    blob.first_path().map(path_cell).unwrap_or_default(),
+                    code(&short_hash(&blob.hash)),
+                ]
+            })
+            .collect();
+        table(&mut out, &["Largest blobs", "Path", "Hash"], rows);
+    }
+
+    if let Some(tags) = &report.tags {
+        out.push_str("\n### Tags\n\n");
+        facts(
+            &mut out,
+            &[
+                ("Annotated tags", tags.total_tags.to_string()),
+                ("Annotated tags size", display_size(tags.total_size_disk)),
+                ("Lightweight tags", tags.lightweight_tags.to_string()),
+            ],
+        );
+        let rows = tags
+            .largest_tags
+            .iter()
+            .map(|tag| {
+                vec![
+                    code(&tag.name),
+                    display_size(tag.size_disk),
+                    code(&short_hash(&tag.hash)),
+                ]
+            })
+            .collect();
+        table(&mut out, &["Largest tags", "Size", "Hash"], rows);
+    }
+
+    if let Some(deltas) = &report.deltas {
+        out.push_str("\n### Deltas\n\n");
+        table(
+            &mut out,
+            &["", "Whole", "Whole size", "Deltas", "Deltas size"],
+            vec![
+                vec![
+                    "All objects".to_string(),
+                    deltas.objects_whole.to_string(),
+                    display_size(deltas.objects_whole_size_disk),
+                    deltas.objects_delta.to_string(),
+                    display_size(deltas.objects_delta_size_disk),
+                ],
+                vec![
+                    "Blobs".to_string(),
+                    deltas.blobs_whole.to_string(),
+                    display_size(deltas.blobs_whole_size_disk),
+                    deltas.blobs_delta.to_string(),
+                    display_size(deltas.blobs_delta_size_disk),
+                ],
+            ],
+        );
+        let rows = deltas
+            .deepest_chains
+            .iter()
+            .map(|chain| {
+                vec![
+                    chain.depth.to_string(),
+                    chain.kind.clone(),
+                    code(&short_hash(&chain.hash)),
+                ]
+            })
+            .collect();
+        table(&mut out, &["Deepest delta chains", "Type", "Hash"], rows);
+    }
+
+    if let Some(branches) = &report.branches {
+        out.push_str("\n### Branches\n\n");
+        let default = branches
+            .default_branch
+            .as_ref()
+            .map_or("none".to_string(), |b| code(&b.name));
+        facts(
+            &mut out,
+            &[
+                ("Default branch", default),
+                ("Total refs", branches.total_refs.to_string()),
+            ],
+        );
+        // The refs are sorted by unique size, so the ones worth deleting come first.
+        let rows = branches
+            .refs
+            .iter()
+            .map(|r| {
+                vec![
+                    code(&r.name),
+                    display_size(r.unique_size),
+                    display_size(r.not_on_default_size),
+                    r.commits_not_on_default.to_string(),
+                ]
+            })
+            .collect();
+        table(
+            &mut out,
+            &[
+                "Ref",
+                "Unique size",
+                "Size not on default",
+                "Commits not on default",
+            ],
+            rows,
+        );
+    }
+
+    truncate(out)
+}
+
+fn commit_row(label: &str, commit: &CommitEntry) -> Vec<String> {
+    vec![
+        label.to_string(),
+        display_size(commit.size_disk),
+        code(&short_hash(&commit.hash)),
+        commit.author_name.as_deref().map(cell).unwrap_or_default(),
+        commit
+            .author_time
+            .map(|time| display_time(time)[..10].to_string())
+            .unwrap_or_default(),
+        commit.subject.as_deref().map(cell).unwrap_or_default(),
+    ]
+}
+
+// The first path of an object, and how many others it has been seen at.
+fn path_cell((path, more): (&str, usize)) -> String {
+    if more == 0 {
+        code(path)
+    } else {
+        format!("{} +{more} more", code(path))
+    }
+}
+
+// A bulleted list of labelled values.
+fn facts(out: &mut String, facts: &[(&str, String)]) {
+    for (label, value) in facts {
+        out.push_str(&format!("- **{label}:** {value}\n"));
+    }
+}
+
+fn table(out: &mut String, headers: &[&str], rows: Vec<Vec<String>>) {
+    if rows.is_empty() {
+        return;
+    }
+
+    if !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+    out.push_str(&format!("| {} |\n", headers.join(" | ")));
+    out.push_str(&format!("|{}\n", " --- |".repeat(headers.len())));
+    let total = rows.len();
+    for row in rows.into_iter().take(MAX_ROWS) {
+        out.push_str(&format!("| {} |\n", row.join(" | ")));
+    }
+    if total > MAX_ROWS {
+        out.push_str(&format!("\n_...and {} more._\n", total - MAX_ROWS));
+    }
+}
+
+// Free text in a table cell. Pipes would end the cell and newlines the row.
+fn cell(text: &str) -> String {
+    shorten(text)
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\n', '\r'], " ")
+}
+
+// Names, paths and hashes as inline code, so markdown in them is not rendered. A backtick in
+// the text would end the span early, so the fence is made one longer than any run of them.
+fn code(text: &str) -> String {
+    let text = shorten(text).replace('|', "\\|").replace(['\n', '\r'], " ");
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run + 1);
+    let pad = if longest_run > 0 { " " } else { "" };
+    format!("{fence}{pad}{text}{pad}{fence}")
+}
+
+fn shorten(text: &str) -> String {
+    if text.chars().count() <= MAX_CELL_CHARS {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(MAX_CELL_CHARS - 1).collect();
+    short.push('…');
+    short
+}
+
+fn short_hash(hash: &str) -> String {
+    hash.chars().take(12).collect()
+}
+
+// Every table is capped, so only a report with a great many sections could get here. Cut it
+// at the last whole line that fits.
+fn truncate(mut out: String) -> String {
+    const NOTE: &str = "\n_Report truncated to fit in a comment._\n";
+    if out.len() <= MAX_MARKDOWN_LEN {
+        return out;
+    }
+    let mut end = MAX_MARKDOWN_LEN - NOTE.len();
+    while !out.is_char_boundary(end) {
+        end -= 1;
+    }
+    let end = out[..end].rfind('\n').map_or(0, |end| end + 1);
+    out.truncate(end);
+    out.push_str(NOTE);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_all::{ObjectEntry, REPORT_FORMAT_VERSION};
+    use crate::report_blobs::BlobReport;
+    use crate::report_branches::{BranchReport, RefSize};
+
+    #[test]
+    fn test_report_markdown() {
+        let report = Report {
+            format_version: REPORT_FORMAT_VERSION,
+            commits: None,
+            trees: None,
+            blobs: Some(BlobReport {
+                total_blobs: 1,
+                total_size_disk: 2048,
+                largest_blobs: vec![
+                    ObjectEntry {
+                        hash: "a".repeat(40),
+                        size_disk: 2048,
+                        paths: vec!["assets/logo.png".to_string(), "logo.png".to_string()],
+                    },
+                    ObjectEntry {
+                        hash: "c".repeat(40),
+                        size_disk: 1024,
+                        paths: vec!["data.bin".to_string()],
+                    },
+                ],
+            }),
+            tags: None,
+            deltas: None,
+            branches: Some(BranchReport {
+                default_branch: None,
+                total_refs: 25,
+                refs: (0..25)
+                    .map(|i| RefSize {
+                        name: format!("refs/heads/a|b`{i}"),
+                        hash: "b".repeat(40),
+                        commits_not_on_default: 1,
+                        not_on_default_size: 100,
+                        unique_size: 100,
+                    })
+                    .collect(),
+            }),
+        };
+
+        let markdown = report_markdown(&report);
+        assert!(markdown.starts_with("## Repository Size Report\n"));
+        assert!(!markdown.contains("### Commits"));
+        assert!(markdown.contains(&format!(
+            "| 2.00 KB | `assets/logo.png` +1 more | `{}` |\n",
+            "a".repeat(12)
+        )));
+        assert!(markdown.contains(&format!(
+            "| 1.00 KB | `data.bin` | `{}` |\n",
+            "c".repeat(12)
+        )));
+        // Pipes and backticks in names do not break out of the table or the code span.
+        assert!(markdown.contains("| `` refs/heads/a\\|b`0 `` | 100 bytes |"));
+        assert!(markdown.contains("_...and 15 more._"));
+        assert_eq!(markdown.matches("refs/heads/").count(), 10);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let long = "- line\n".repeat(MAX_MARKDOWN_LEN);
+        let truncated = truncate(long);
+        assert!(truncated.len() <= MAX_MARKDOWN_LEN);
+        assert!(truncated.ends_with("_Report truncated to fit in a comment._\n"));
+        assert!(truncated.contains("- line\n\n_Report"));
+
+        // The cut can land inside a character, it must not split it.
+        for prefix in ["", "x", "xx"] {
+            let long = format!("{prefix}{}", "- ☃☃☃\n".repeat(MAX_MARKDOWN_LEN));
+            let truncated = truncate(long);
+            assert!(truncated.len() <= MAX_MARKDOWN_LEN);
+            assert!(truncated.contains("- ☃☃☃\n\n_Report"));
+        }
+    }
+}
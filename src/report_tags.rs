@@ -0,0 +1,99 @@
+use crate::object_collection::{ObjectContainer, Properties};
+use crate::utils::{display_size, keep_largest};
+use serde::Serialize;
+use std::time::Instant;
+
+/// Totals for the annotated tag objects and the lightweight tags, and the largest tags.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagReport {
+    pub total_tags: usize,
+    pub total_size_disk: u64,
+    /// Tags that are only a ref, with no tag object.
+    pub lightweight_tags: usize,
+    /// The 10 largest annotated tags on disk, largest first.
+    pub largest_tags: Vec<TagEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagEntry {
+    /// The full ref name, such as `refs/tags/v1.0`, empty if the tags were not read.
+    pub name: String,
+    pub hash: String,
+    pub size_disk: u64,
+    /// The commit the tag points at, if it is in the container.
+    pub commit: Option<String>,
+}
+
+pub fn report_tags(container: &ObjectContainer) {
+    print_tag_report(&build_tag_report(container));
+}
+
+pub fn build_tag_report(container: &ObjectContainer) -> TagReport {
+    eprintln!("Building tag report...");
+    let start = Instant::now();
+
+    let mut total_size: u64 = 0;
+    // (size, index) of every tag, then only the largest.
+    let mut largest: Vec<(u64, usize)> = Vec::new();
+    for rw_tag in container.tags().object_iter() {
+        let tag = rw_tag.read().unwrap();
+        total_size += tag.size_disk();
+        largest.push((tag.size_disk(), *tag.hash_index()));
+    }
+    keep_largest(&mut largest, 10);
+
+    let lightweight_tags = container
+        .commits()
+        .object_iter()
+        .map(|commit| commit.read().unwrap().lightweight_tags().len())
+        .sum();
+
+    let report = TagReport {
+        total_tags: container.tags().count(),
+        total_size_disk: total_size,
+        lightweight_tags,
+        largest_tags: largest
+            .into_iter()
+            .map(|(size_disk, index)| {
+                let tag = container.tags().get_by_index(&index).read().unwrap();
+                TagEntry {
+                    name: tag.name().to_string(),
+                    hash: container
+                        .tags()
+                        .lookup_hash_for_index(&index)
+                        .unwrap()
+                        .to_string(),
+                    size_disk,
+                    commit: tag
+                        .commit_dep()
+                        .and_then(|commit| container.commits().lookup_hash_for_index(&commit))
+                        .map(ToString::to_string),
+                }
+            })
+            .collect(),
+    };
+    eprintln!("Tag report created in: {:?}", start.elapsed());
+    report
+}
+
+pub fn print_tag_report(report: &TagReport) {
+    println!();
+    println!("Tag Report");
+    println!("-------------------------------------------------------");
+    println!("Total Annotated Tags: {}", report.total_tags);
+    println!(
+        "Total Annotated Tags Size: {}",
+        display_size(report.total_size_disk)
+    );
+    println!("Total Lightweight Tags: {}", report.lightweight_tags);
+    println!("Top 10 Largest Tags:");
+    for tag in &report.largest_tags {
+        println!(
+            "\tTag Size: {}, Name: {}, Hash: {}, Commit: {}",
+            display_size(tag.size_disk),
+            tag.name,
+            tag.hash,
+            tag.commit.as_deref().unwrap_or("unknown")
+        );
+    }
+}
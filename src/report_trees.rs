@@ -1,30 +1,52 @@
 use crate::object_collection::{ObjectContainer, Properties};
+use crate::report_all::ObjectEntry;
 use crate::utils::display_size;
+use serde::Serialize;
 use std::{collections::HashMap, time::Instant};
 
+/// Totals for every tree, and the path that has had the most trees.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TreeReport {
+    pub total_trees: usize,
+    pub total_size_disk: u64,
+    pub largest_tree: Option<ObjectEntry>,
+    pub most_trees_at_path: Option<PathEntry>,
+}
+
+/// A directory along with how many distinct trees have been seen at it and their total size.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PathEntry {
+    pub path: String,
+    pub count: usize,
+    pub total_size_disk: u64,
+}
+
 pub fn report_trees(container: &ObjectContainer) {
-    println!("Building tree report...");
+    print_tree_report(&build_tree_report(container));
+}
+
+pub fn build_tree_report(container: &ObjectContainer) -> TreeReport {
+    eprintln!("Building tree report...");
     let start = Instant::now();
     let mut total_size: u64 = 0;
-    let mut largest_tree_size: u32 = 0;
+    let mut largest_tree_size: u64 = 0;
     let mut largest_tree_index: usize = 0;
-    let mut tree_collector: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut tree_collector: HashMap<u32, Vec<usize>> = HashMap::new();
 
     for rw_tree in container.trees().object_iter() {
         let tree = rw_tree.read().unwrap();
-        total_size += tree.size_disk() as u64;
+        total_size += tree.size_disk();
         if largest_tree_size < tree.size_disk() {
             largest_tree_size = tree.size_disk();
             largest_tree_index = *tree.hash_index();
         }
 
-        match tree_collector.get_mut(tree.path()) {
-            Some(trees) => {
-                trees.push(*tree.hash_index());
-            }
-            None => {
-                tree_collector.insert(tree.path().to_string(), vec![*tree.hash_index()]);
-            }
+        // A tree counts towards every path it has been seen at.
+        for path_id in tree.paths() {
+            tree_collector
+                .entry(*path_id)
+                .or_default()
+                .push(*tree.hash_index());
         }
     }
 
@@ -32,41 +54,72 @@ pub fn report_trees(container: &ObjectContainer) {
     let mut most_trees_at_path_count: usize = 0;
     let mut most_trees_at_path: String = String::new();
     let mut most_trees_at_path_total_size: u64 = 0;
+    let mut most_trees_at_path_id: Option<u32> = None;
 
-    for (path, trees) in &tree_collector {
-        if most_trees_at_path_count < trees.len() {
+    for (path_id, trees) in &tree_collector {
+        let path = container.resolve_path(*path_id).unwrap_or_default();
+        // break ties on the path so the report is the same from run to run.
+        if most_trees_at_path_count < trees.len()
+            || (most_trees_at_path_count == trees.len() && *path < *most_trees_at_path)
+        {
             most_trees_at_path_count = trees.len();
-            most_trees_at_path = path.clone();
+            most_trees_at_path = path;
+            most_trees_at_path_id = Some(*path_id);
         }
     }
 
-    let trees = tree_collector.get(&most_trees_at_path).unwrap();
-    for tree_index in trees {
-        let tree = container.trees().get_by_index(tree_index);
-        most_trees_at_path_total_size += tree.read().unwrap().size_disk() as u64;
+    if let Some(trees) = most_trees_at_path_id.and_then(|id| tree_collector.get(&id)) {
+        for tree_index in trees {
+            let tree = container.trees().get_by_index(tree_index);
+            most_trees_at_path_total_size += tree.read().unwrap().size_disk();
+        }
     }
 
+    let report = TreeReport {
+        total_trees: container.trees().count(),
+        total_size_disk: total_size,
+        largest_tree: container
+            .trees()
+            .lookup_hash_for_index(&largest_tree_index)
+            .map(|hash| ObjectEntry {
+                hash: hash.to_string(),
+                size_disk: largest_tree_size,
+                paths: container.resolve_paths(
+                    container
+                        .trees()
+                        .get_by_index(&largest_tree_index)
+                        .read()
+                        .unwrap()
+                        .paths(),
+                ),
+            }),
+        most_trees_at_path: most_trees_at_path_id.map(|_| PathEntry {
+            path: most_trees_at_path,
+            count: most_trees_at_path_count,
+            total_size_disk: most_trees_at_path_total_size,
+        }),
+    };
+    eprintln!("Tree report created in: {:?}", start.elapsed());
+    report
+}
+
+pub fn print_tree_report(report: &TreeReport) {
     println!();
     println!("Tree Report");
     println!("-------------------------------------------------------");
-    println!("Total Trees: {}", container.trees().count());
-    println!("Total Trees Size: {}", display_size(total_size));
-    println!(
-        "Largest Tree Object Size: {}",
-        display_size(largest_tree_size as u64)
-    );
-    println!(
-        "Largest Tree Object Id: {}",
-        container
-            .commits()
-            .lookup_hash_for_index(&largest_tree_index)
-            .unwrap()
-    );
-    println!("Most Trees at Path: {most_trees_at_path}");
-    println!("Count Most Trees at Path: {most_trees_at_path_count}");
-    println!(
-        "Most Trees at Path Total Size: {}\n\n",
-        display_size(most_trees_at_path_total_size)
-    );
-    println!("Tree report created in: {:?}", start.elapsed());
+    println!("Total Trees: {}", report.total_trees);
+    println!("Total Trees Size: {}", display_size(report.total_size_disk));
+    if let Some(tree) = &report.largest_tree {
+        println!("Largest Tree Object Size: {}", display_size(tree.size_disk));
+        println!("Largest Tree Object Id: {}", tree.hash);
+    }
+    if let Some(path) = &report.most_trees_at_path {
+        println!("Most Trees at Path: {}", path.path);
+        println!("Count Most Trees at Path: {}", path.count);
+        println!(
+            "Most Trees at Path Total Size: {}",
+            display_size(path.total_size_disk)
+        );
+    }
+    println!("\n");
 }
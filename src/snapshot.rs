@@ -0,0 +1,775 @@
+use crate::blob::Blob;
+use crate::commit::{Commit, CommitMetadata};
+use crate::error::{Error, Result};
+use crate::object_collection::{
+    BasicObjectContainer, Delta, ObjectContainer, PathTable, Properties,
+};
+use crate::object_id::{ObjectFormat, ObjectId};
+use crate::object_source::Scope;
+use crate::pack::read_object_format;
+use crate::tag::Tag;
+use crate::tree::Tree;
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// Every snapshot starts with these bytes followed by the format version.
+const MAGIC: &[u8; 8] = b"GDAGSNAP";
+
+/// The version of the snapshot format written by `save_snapshot`. Bump this whenever the
+/// layout changes, older snapshots are then rejected rather than misread.
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+/// What a snapshot was taken of. A snapshot is only loaded for the repo, object format and
+/// scope it was taken with, as one taken of anything else would report the wrong objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub repo_path: String,
+    pub object_format: ObjectFormat,
+    /// The `git rev-list` arguments the scope was read with, `--all` for every ref.
+    pub scope: Vec<String>,
+}
+
+impl SnapshotHeader {
+    /// The header for a snapshot of the repo at `repo_path` limited to `scope`.
+    pub fn new(repo_path: &Path, scope: &Scope) -> Result<Self> {
+        Ok(SnapshotHeader {
+            repo_path: repo_path
+                .canonicalize()
+                .unwrap_or_else(|_| repo_path.to_path_buf())
+                .display()
+                .to_string(),
+            object_format: read_object_format(repo_path)?,
+            scope: scope.rev_list_args(),
+        })
+    }
+
+    // Why a snapshot with this header can not be used in place of one with `expected`, None
+    // if it can.
+    fn mismatch(&self, expected: &SnapshotHeader) -> Option<String> {
+        if self.repo_path != expected.repo_path {
+            Some(format!("taken of {}", self.repo_path))
+        } else if self.object_format != expected.object_format {
+            Some(format!("taken of a {} repo", self.object_format.name()))
+        } else if self.scope != expected.scope {
+            Some(format!("taken of `{}`", self.scope.join(" ")))
+        } else {
+            None
+        }
+    }
+}
+
+/// Write the fully linked container to `path` so it can be loaded back with `load_snapshot`
+/// without reading the repo again.
+///
+/// The layout is a header ( magic, version, `header`, id length and the number of each
+/// object type ) followed by the strings, the path table, the commits, trees, blobs and tags
+/// in index order, then the refs. Integers are written as LEB128 varints, which keeps
+/// indexes and most sizes to a byte or two, and times as 8 little endian bytes.
+///
+/// The snapshot is written next to `path` and moved into place once complete, so a save that
+/// fails or is interrupted leaves any earlier snapshot at `path` as it was.
+pub fn save_snapshot(
+    container: &ObjectContainer,
+    path: &Path,
+    header: &SnapshotHeader,
+) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut out = SnapshotWriter {
+        out: BufWriter::new(File::create(&temp_path)?),
+    };
+
+    out.bytes(MAGIC)?;
+    out.bytes(&SNAPSHOT_VERSION.to_le_bytes())?;
+    out.str(&header.repo_path)?;
+    out.str(header.object_format.name())?;
+    out.varint(header.scope.len() as u64)?;
+    for arg in &header.scope {
+        out.str(arg)?;
+    }
+    out.varint(id_len(container) as u64)?;
+    out.varint(container.commits().count() as u64)?;
+    out.varint(container.trees().count() as u64)?;
+    out.varint(container.blobs().count() as u64)?;
+    out.varint(container.tags().count() as u64)?;
+
+    {
+        let strings = container.strings().read().unwrap();
+        out.varint(strings.count() as u64)?;
+        for id in 0..strings.count() as u32 {
+            out.str(strings.resolve(id).map_or("", |s| s))?;
+        }
+    }
+
+    {
+        // The root is always present, so only the paths after it are written. A path always
+        // comes after its parent, so they can be added back in the same order.
+        let paths = container.paths().read().unwrap();
+        out.varint(paths.count() as u64 - 1)?;
+        for id in 1..paths.count() as u32 {
+            out.varint(u64::from(paths.parent(id).unwrap_or(PathTable::ROOT)))?;
+            out.str(paths.name(id).unwrap_or_default())?;
+        }
+    }
+
+    write_objects(&mut out, container.commits(), |out, commit| {
+        out.sizes(commit.size(), commit.size_disk())?;
+        out.varint(u64::from(commit.deps_processed()))?;
+        out.indexes(commit.parents())?;
+        out.indexes(commit.children())?;
+        match commit.metadata() {
+            Some(metadata) => {
+                out.varint(1)?;
+                out.varint(u64::from(metadata.author_name))?;
+                out.varint(u64::from(metadata.author_email))?;
+                out.bytes(&metadata.author_time.to_le_bytes())?;
+                out.bytes(&metadata.commit_time.to_le_bytes())?;
+                out.varint(u64::from(metadata.subject))?;
+            }
+            None => out.varint(0)?,
+        }
+        out.indexes(commit.blob_deps())?;
+        out.indexes(commit.tree_deps())?;
+        out.indexes(commit.tag_deps())?;
+        out.varint(commit.lightweight_tags().len() as u64)?;
+        for label in commit.lightweight_tags() {
+            out.str(label)?;
+        }
+        Ok(())
+    })?;
+    write_objects(&mut out, container.trees(), |out, tree| {
+        out.sizes(tree.size(), tree.size_disk())?;
+        out.path_ids(tree.paths())?;
+        out.indexes(tree.commits())
+    })?;
+    write_objects(&mut out, container.blobs(), |out, blob| {
+        out.sizes(blob.size(), blob.size_disk())?;
+        out.path_ids(blob.paths())?;
+        out.indexes(blob.commits())
+    })?;
+    write_objects(&mut out, container.tags(), |out, tag| {
+        out.sizes(tag.size(), tag.size_disk())?;
+        out.str(tag.name())?;
+        out.optional_index(tag.commit_dep())
+    })?;
+
+    let refs = container.refs();
+    out.varint(refs.len() as u64)?;
+    for (name, commit) in &refs {
+        out.str(name)?;
+        out.varint(*commit as u64)?;
+    }
+
+    out.out.flush()?;
+    drop(out);
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Read a container written by `save_snapshot`. Fails with `Error::CacheCorrupt` if the file
+/// is not a snapshot, was written by a different version or is truncated, and with
+/// `Error::SnapshotMismatch` if it was taken of a different repo or scope than `expected`.
+pub fn load_snapshot(path: &Path, expected: &SnapshotHeader) -> Result<ObjectContainer> {
+    let data = std::fs::read(path)?;
+    let mut input = SnapshotReader {
+        data: &data,
+        position: 0,
+        path: path.to_path_buf(),
+    };
+
+    if input.take(MAGIC.len())? != MAGIC {
+        return Err(input.corrupt("not a snapshot"));
+    }
+    let version = u32::from_le_bytes(input.array()?);
+    if version != SNAPSHOT_VERSION {
+        return Err(input.corrupt(&format!(
+            "snapshot version {version} is not supported, expected {SNAPSHOT_VERSION}"
+        )));
+    }
+
+    let repo_path = input.str()?;
+    let format = input.str()?;
+    let Some(object_format) = ObjectFormat::from_name(&format) else {
+        return Err(input.corrupt(&format!("unknown object format {format}")));
+    };
+    let mut scope = Vec::new();
+    for _ in 0..input.length()? {
+        scope.push(input.str()?);
+    }
+    let header = SnapshotHeader {
+        repo_path,
+        object_format,
+        scope,
+    };
+    if let Some(reason) = header.mismatch(expected) {
+        return Err(Error::SnapshotMismatch {
+            path: path.to_path_buf(),
+            reason,
+        });
+    }
+
+    let id_len = match usize::try_from(input.varint()?) {
+        Ok(len @ (ObjectId::SHA1_LEN | ObjectId::SHA256_LEN)) => len,
+        _ => return Err(input.corrupt("object ids must be 20 or 32 bytes")),
+    };
+    let mut counts = Counts {
+        commits: input.length()?,
+        trees: input.length()?,
+        blobs: input.length()?,
+        tags: input.length()?,
+        strings: 0,
+        paths: 0,
+    };
+
+    let mut container = ObjectContainer::new();
+
+    counts.strings = input.length()?;
+    {
+        let mut strings = container.strings().write().unwrap();
+        for _ in 0..counts.strings {
+            let value = input.str()?;
+            strings.intern(&value);
+        }
+        // Each string was unique when saved, so they must all have their own id again.
+        if strings.count() != counts.strings {
+            return Err(input.corrupt("duplicate interned string"));
+        }
+    }
+
+    counts.paths = input.length()? + 1;
+    {
+        let mut paths = container.paths().write().unwrap();
+        for id in 1..counts.paths {
+            let parent = input.index(id)?;
+            let name = input.str()?;
+            if paths.intern_child(parent as u32, &name) as usize != id {
+                return Err(input.corrupt("duplicate path"));
+            }
+        }
+    }
+
+    read_objects(
+        &mut input,
+        id_len,
+        counts.commits,
+        container.mut_commits(),
+        |input, index| {
+            let mut commit = Commit::new(index, input.varint()?, input.varint()?);
+            if input.varint()? != 0 {
+                commit.set_deps_processed();
+            }
+            for parent in input.indexes(counts.commits)? {
+                commit.add_parent(&parent);
+            }
+            for child in input.indexes(counts.commits)? {
+                commit.add_child(&child);
+            }
+            if input.varint()? != 0 {
+                commit.set_metadata(CommitMetadata {
+                    author_name: input.string_id(counts.strings)?,
+                    author_email: input.string_id(counts.strings)?,
+                    author_time: i64::from_le_bytes(input.array()?),
+                    commit_time: i64::from_le_bytes(input.array()?),
+                    subject: input.string_id(counts.strings)?,
+                });
+            }
+            for blob in input.indexes(counts.blobs)? {
+                commit.add_blob_dep(&blob);
+            }
+            for tree in input.indexes(counts.trees)? {
+                commit.add_tree_dep(&tree);
+            }
+            for tag in input.indexes(counts.tags)? {
+                commit.add_tag_dep(&tag);
+            }
+            for _ in 0..input.length()? {
+                commit.add_lightweight_tag(&input.str()?);
+            }
+            Ok(commit)
+        },
+    )?;
+    read_objects(
+        &mut input,
+        id_len,
+        counts.trees,
+        container.mut_trees(),
+        |input, index| {
+            let mut tree = Tree::new(index, input.varint()?, input.varint()?);
+            for path in input.indexes(counts.paths)? {
+                tree.add_path(path as u32);
+            }
+            for commit in input.indexes(counts.commits)? {
+                tree.add_commit(&commit);
+            }
+            Ok(tree)
+        },
+    )?;
+    read_objects(
+        &mut input,
+        id_len,
+        counts.blobs,
+        container.mut_blobs(),
+        |input, index| {
+            let mut blob = Blob::new(index, input.varint()?, input.varint()?);
+            for path in input.indexes(counts.paths)? {
+                blob.add_path(path as u32);
+            }
+            for commit in input.indexes(counts.commits)? {
+                blob.add_commit(&commit);
+            }
+            Ok(blob)
+        },
+    )?;
+    read_objects(
+        &mut input,
+        id_len,
+        counts.tags,
+        container.mut_tags(),
+        |input, index| {
+            let mut tag = Tag::new(index, input.varint()?, input.varint()?);
+            tag.add_name(&input.str()?);
+            if let Some(commit) = input.optional_index(counts.commits)? {
+                tag.add_commit_dep(&commit);
+            }
+            Ok(tag)
+        },
+    )?;
+
+    let mut refs = Vec::new();
+    for _ in 0..input.length()? {
+        let name = input.str()?;
+        refs.push((name, input.index(counts.commits)?));
+    }
+    container.set_refs(refs);
+
+    if input.position != data.len() {
+        return Err(input.corrupt("unexpected data after the refs"));
+    }
+    Ok(container)
+}
+
+// All ids in a repo have the same length, so it is only written once.
+fn id_len(container: &ObjectContainer) -> usize {
+    [
+        container.commits().lookup_hash_for_index(&0),
+        container.trees().lookup_hash_for_index(&0),
+        container.blobs().lookup_hash_for_index(&0),
+        container.tags().lookup_hash_for_index(&0),
+    ]
+    .into_iter()
+    .flatten()
+    .next()
+    .map_or(ObjectId::SHA1_LEN, ObjectId::len)
+}
+
+// Write the id and delta information every object type has, then hand over to
+// `write_object` for the rest, starting with its sizes.
+fn write_objects<T, W: Write>(
+    out: &mut SnapshotWriter<W>,
+    objects: &BasicObjectContainer<T>,
+    write_object: impl Fn(&mut SnapshotWriter<W>, &T) -> Result<()>,
+) -> Result<()>
+where
+    T: Properties + Delta,
+{
+    for index in 0..objects.count() {
+        let object = objects.get_by_index(&index).read().unwrap();
+        let Some(id) = objects.lookup_hash_for_index(&index) else {
+            continue;
+        };
+        out.bytes(id.as_bytes())?;
+        out.optional_index(object.delta_base())?;
+        out.varint(u64::from(object.delta_depth()))?;
+        write_object(out, &object)?;
+    }
+    Ok(())
+}
+
+// The reverse of `write_objects`, `read_object` is given the index of the object and builds it
+// from the rest of its fields.
+fn read_objects<T>(
+    input: &mut SnapshotReader,
+    id_len: usize,
+    count: usize,
+    objects: &mut BasicObjectContainer<T>,
+    read_object: impl Fn(&mut SnapshotReader, usize) -> Result<T>,
+) -> Result<()>
+where
+    T: Properties + Delta,
+{
+    for index in 0..count {
+        // The length was checked when reading the header.
+        let id = ObjectId::from_bytes(input.take(id_len)?).unwrap();
+        let delta_base = input.optional_index(count)?;
+        let delta_depth = u32::try_from(input.varint()?)
+            .map_err(|_| input.corrupt("delta depth out of range"))?;
+
+        let mut object = read_object(input, index)?;
+        object.set_delta(delta_base, delta_depth);
+        if objects.get_index(&id).is_some() {
+            return Err(input.corrupt(&format!("object {id} is listed twice")));
+        }
+        objects.add(id, object);
+    }
+    Ok(())
+}
+
+// The number of each kind of item in the snapshot, every index read is checked against these.
+struct Counts {
+    commits: usize,
+    trees: usize,
+    blobs: usize,
+    tags: usize,
+    strings: usize,
+    paths: usize,
+}
+
+struct SnapshotWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn varint(&mut self, mut value: u64) -> Result<()> {
+        let mut buffer = [0u8; 10];
+        let mut len = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                buffer[len] = byte;
+                len += 1;
+                break;
+            }
+            buffer[len] = byte | 0x80;
+            len += 1;
+        }
+        self.bytes(&buffer[..len])
+    }
+
+    fn sizes(&mut self, size: u64, size_disk: u64) -> Result<()> {
+        self.varint(size)?;
+        self.varint(size_disk)
+    }
+
+    fn str(&mut self, value: &str) -> Result<()> {
+        self.varint(value.len() as u64)?;
+        self.bytes(value.as_bytes())
+    }
+
+    fn indexes(&mut self, indexes: &[usize]) -> Result<()> {
+        self.varint(indexes.len() as u64)?;
+        for index in indexes {
+            self.varint(*index as u64)?;
+        }
+        Ok(())
+    }
+
+    fn path_ids(&mut self, ids: &[u32]) -> Result<()> {
+        self.varint(ids.len() as u64)?;
+        for id in ids {
+            self.varint(u64::from(*id))?;
+        }
+        Ok(())
+    }
+
+    // Written as the index plus one, so None can be zero.
+    fn optional_index(&mut self, index: Option<usize>) -> Result<()> {
+        self.varint(index.map_or(0, |index| index as u64 + 1))
+    }
+}
+
+struct SnapshotReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    path: PathBuf,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn corrupt(&self, reason: &str) -> Error {
+        Error::CacheCorrupt {
+            path: self.path.clone(),
+            reason: format!("{reason} at byte {}", self.position),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| self.corrupt("unexpected end of file"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.corrupt("varint too long"))
+    }
+
+    // A count of items that follow. Every item takes at least a byte, so a count larger than
+    // what is left of the file can only come from a corrupt file.
+    fn length(&mut self) -> Result<usize> {
+        let value = self.varint()?;
+        usize::try_from(value)
+            .ok()
+            .filter(|&len| len <= self.data.len() - self.position)
+            .ok_or_else(|| self.corrupt("length out of range"))
+    }
+
+    fn index(&mut self, count: usize) -> Result<usize> {
+        let value = self.varint()?;
+        usize::try_from(value)
+            .ok()
+            .filter(|&index| index < count)
+            .ok_or_else(|| self.corrupt("index out of range"))
+    }
+
+    fn optional_index(&mut self, count: usize) -> Result<Option<usize>> {
+        match self.index(count + 1)? {
+            0 => Ok(None),
+            index => Ok(Some(index - 1)),
+        }
+    }
+
+    fn indexes(&mut self, count: usize) -> Result<Vec<usize>> {
+        let len = self.length()?;
+        (0..len).map(|_| self.index(count)).collect()
+    }
+
+    fn string_id(&mut self, count: usize) -> Result<u32> {
+        self.index(count).map(|id| id as u32)
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.length()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.corrupt("invalid utf-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_source::{InMemorySource, ObjectKind};
+    use tempfile::TempDir;
+
+    const FIRST_COMMIT: &str = "1111111111111111111111111111111111111111";
+    const SECOND_COMMIT: &str = "2222222222222222222222222222222222222222";
+    const FIRST_TREE: &str = "3333333333333333333333333333333333333333";
+    const SECOND_TREE: &str = "4444444444444444444444444444444444444444";
+    const FIRST_BLOB: &str = "5555555555555555555555555555555555555555";
+    const SECOND_BLOB: &str = "6666666666666666666666666666666666666666";
+    const TAG: &str = "7777777777777777777777777777777777777777";
+
+    fn header() -> SnapshotHeader {
+        SnapshotHeader {
+            repo_path: "/repo".to_string(),
+            object_format: ObjectFormat::Sha1,
+            scope: Scope::all().rev_list_args(),
+        }
+    }
+
+    async fn build_container() -> ObjectContainer {
+        let mut source = InMemorySource::new();
+        source.add_object(ObjectKind::Commit, FIRST_COMMIT, 200, 150);
+        source.add_object(ObjectKind::Commit, SECOND_COMMIT, 210, 160);
+        source.add_object(ObjectKind::Tree, FIRST_TREE, 40, 35);
+        source.add_object(ObjectKind::Tree, SECOND_TREE, 80, 20);
+        source.add_object(ObjectKind::Blob, FIRST_BLOB, 5_000_000_000, 1000);
+        source.add_object(ObjectKind::Blob, SECOND_BLOB, 2000, 30);
+        source.add_object(ObjectKind::Tag, TAG, 150, 140);
+        source.set_delta_base(SECOND_BLOB, FIRST_BLOB);
+        source.add_commit_dep(FIRST_COMMIT, FIRST_TREE, "");
+        source.add_commit_dep(FIRST_COMMIT, FIRST_BLOB, "src/main.rs");
+        source.add_commit_dep(SECOND_COMMIT, SECOND_TREE, "");
+        source.add_commit_dep(SECOND_COMMIT, SECOND_BLOB, "src/main.rs");
+        source.add_commit_parents(FIRST_COMMIT, &[]);
+        source.add_commit_parents(SECOND_COMMIT, &[FIRST_COMMIT]);
+        source.add_commit_metadata(
+            FIRST_COMMIT,
+            ("Jane Doe", "jane@example.com"),
+            1_600_000_000,
+            1_600_000_100,
+            "Initial commit",
+        );
+        source.add_tag_ref("v1.0", TAG, Some(SECOND_COMMIT));
+        source.add_tag_ref("nightly", FIRST_COMMIT, None);
+        source.add_ref("refs/heads/main", SECOND_COMMIT);
+
+        let container = source.into_container().await;
+        container
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo.snapshot");
+        let container = build_container().await;
+        save_snapshot(&container, &path, &header()).unwrap();
+        let loaded = load_snapshot(&path, &header()).unwrap();
+
+        assert_eq!(loaded.commits().count(), 2);
+        assert_eq!(loaded.trees().count(), 2);
+        assert_eq!(loaded.blobs().count(), 2);
+        assert_eq!(loaded.tags().count(), 1);
+        for index in 0..2 {
+            assert_eq!(
+                loaded.commits().lookup_hash_for_index(&index),
+                container.commits().lookup_hash_for_index(&index)
+            );
+        }
+
+        let second = loaded.commits().get_by_hex(SECOND_COMMIT).unwrap();
+        let second = second.read().unwrap();
+        let first_index = *loaded.commits().get_index_by_hex(FIRST_COMMIT).unwrap();
+        assert_eq!(second.size(), 210);
+        assert_eq!(second.size_disk(), 160);
+        assert_eq!(second.parents(), &vec![first_index]);
+        assert_eq!(second.blob_deps().len(), 1);
+        assert_eq!(second.tree_deps().len(), 1);
+        assert_eq!(second.tag_deps().len(), 1);
+
+        let first = loaded.commits().get_by_index(&first_index).read().unwrap();
+        assert_eq!(first.children().len(), 1);
+        assert_eq!(
+            first.lightweight_tags(),
+            &vec!["refs/tags/nightly".to_string()]
+        );
+        let metadata = first.metadata().unwrap();
+        assert_eq!(metadata.author_time, 1_600_000_000);
+        assert_eq!(
+            loaded.resolve_string(metadata.subject).as_deref(),
+            Some("Initial commit")
+        );
+
+        let blob = loaded.blobs().get_by_hex(SECOND_BLOB).unwrap();
+        let blob = blob.read().unwrap();
+        assert_eq!(blob.delta_depth(), 1);
+        assert_eq!(
+            blob.delta_base(),
+            loaded.blobs().get_index_by_hex(FIRST_BLOB).copied()
+        );
+        assert_eq!(blob.commits().len(), 1);
+        assert_eq!(loaded.resolve_paths(blob.paths()), vec!["src/main.rs"]);
+        assert_eq!(loaded.find_path("src"), container.find_path("src"));
+
+        let first_blob = loaded.blobs().get_by_hex(FIRST_BLOB).unwrap();
+        assert_eq!(first_blob.read().unwrap().size(), 5_000_000_000);
+
+        let tag = loaded.tags().get_by_index(&0).read().unwrap();
+        assert_eq!(tag.name(), "refs/tags/v1.0");
+        assert_eq!(
+            tag.commit_dep(),
+            loaded.commits().get_index_by_hex(SECOND_COMMIT).copied()
+        );
+        assert_eq!(loaded.refs().len(), 3);
+        assert_eq!(loaded.refs(), container.refs());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_save_keeps_old_file_on_failure() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo.snapshot");
+        let container = build_container().await;
+        save_snapshot(&container, &path, &header()).unwrap();
+        let saved = std::fs::read(&path).unwrap();
+        assert!(!dir.path().join("repo.snapshot.tmp").exists());
+
+        // Saving again replaces the file in one step.
+        save_snapshot(&container, &path, &header()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), saved);
+
+        // A save that can not write its temporary file leaves the old snapshot alone.
+        std::fs::create_dir(dir.path().join("repo.snapshot.tmp")).unwrap();
+        assert!(save_snapshot(&ObjectContainer::new(), &path, &header()).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), saved);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_rejects_corrupt_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo.snapshot");
+        save_snapshot(&build_container().await, &path, &header()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        let corrupt = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            matches!(
+                load_snapshot(&path, &header()),
+                Err(Error::CacheCorrupt { .. })
+            )
+        };
+
+        assert!(corrupt(b"commit 1111"));
+        assert!(corrupt(&data[..data.len() - 1]));
+        assert!(corrupt(&[data.as_slice(), &[0]].concat()));
+
+        let mut newer = data.clone();
+        newer[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(corrupt(&newer));
+    }
+
+    #[test]
+    fn test_snapshot_empty_container() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("empty.snapshot");
+        save_snapshot(&ObjectContainer::new(), &path, &header()).unwrap();
+
+        let loaded = load_snapshot(&path, &header()).unwrap();
+        assert_eq!(loaded.commits().count(), 0);
+        assert_eq!(loaded.paths().read().unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_rejects_other_repos() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo.snapshot");
+        save_snapshot(&build_container().await, &path, &header()).unwrap();
+
+        let other_repo = SnapshotHeader {
+            repo_path: "/other".to_string(),
+            ..header()
+        };
+        let other_format = SnapshotHeader {
+            object_format: ObjectFormat::Sha256,
+            ..header()
+        };
+        let other_scope = SnapshotHeader {
+            scope: Scope::new(&["main".to_string()], &[])
+                .unwrap()
+                .rev_list_args(),
+            ..header()
+        };
+        for expected in [other_repo, other_format, other_scope] {
+            assert!(matches!(
+                load_snapshot(&path, &expected),
+                Err(Error::SnapshotMismatch { .. })
+            ));
+        }
+    }
+}
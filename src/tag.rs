@@ -1,26 +1,34 @@
-use crate::object_collection::Properties;
+use crate::object_collection::{impl_delta, Properties};
 
 pub struct Tag {
     hash_index: usize,
-    size: u32,
-    size_disk: u32,
+    size: u64,
+    size_disk: u64,
+    delta_base: Option<usize>,
+    delta_depth: u32,
     name: String,
     commit_index: Option<usize>,
 }
 
 impl Tag {
     // Constructor
-    pub fn new(hash_index: usize, size: u32, size_disk: u32) -> Tag {
+    pub fn new(hash_index: usize, size: u64, size_disk: u64) -> Tag {
         Tag {
             hash_index,
             size,
             size_disk,
+            delta_base: None,
+            delta_depth: 0,
             name: "".to_string(),
             commit_index: None,
         }
     }
 
-    pub fn size_disk(&self) -> u32 {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn size_disk(&self) -> u64 {
         self.size_disk
     }
 
@@ -28,10 +36,19 @@ impl Tag {
         self.name = name.to_string();
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn add_commit_dep(&mut self, commit_index: &usize) {
         self.commit_index = Some(*commit_index);
     }
 
+    /// The commit this tag points at, if it is in the container.
+    pub fn commit_dep(&self) -> Option<usize> {
+        self.commit_index
+    }
+
     // Method to display tree information
     pub fn display_info(&self) {
         println!("Hash: {}", self.hash_index);
@@ -49,3 +66,5 @@ impl Properties for Tag {
         self.hash_index = index;
     }
 }
+
+impl_delta!(Tag);
@@ -1,41 +1,61 @@
-use crate::object_collection::Properties;
+use crate::object_collection::{impl_delta, Properties};
 
 pub struct Tree {
     hash_index: usize,
-    size: u32,
-    size_disk: u32,
-    path: String,
+    size: u64,
+    size_disk: u64,
+    delta_base: Option<usize>,
+    delta_depth: u32,
+    paths: Vec<u32>,
     commits: Vec<usize>,
 }
 
 impl Tree {
     // Constructor
-    pub fn new(hash_index: usize, size: u32, size_disk: u32) -> Self {
+    pub fn new(hash_index: usize, size: u64, size_disk: u64) -> Self {
         Tree {
             hash_index,
             size,
             size_disk,
-            path: "".to_string(),
+            delta_base: None,
+            delta_depth: 0,
+            paths: Vec::new(),
             commits: Vec::new(),
         }
     }
 
-    pub fn size_disk(&self) -> u32 {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn size_disk(&self) -> u64 {
         self.size_disk
     }
 
-    pub fn path(&self) -> &str {
-        &self.path
+    /// Record a directory this tree was committed as, as an id from the containers path
+    /// table. Each path is only kept once.
+    pub fn add_path(&mut self, path_id: u32) {
+        if let Err(position) = self.paths.binary_search(&path_id) {
+            self.paths.insert(position, path_id);
+        }
     }
 
-    pub fn add_path(&mut self, path: &str) {
-        self.path = path.to_string();
+    /// The directories this tree was committed as, as path table ids. A root tree has the
+    /// root id, the empty path, and an identical directory in several places shares one tree.
+    /// The order follows the order commits were read in, see `ObjectContainer::resolve_paths`
+    /// for the paths sorted.
+    pub fn paths(&self) -> &Vec<u32> {
+        &self.paths
     }
 
     pub fn add_commit(&mut self, commit_index: &usize) {
         self.commits.push(*commit_index);
     }
 
+    pub fn commits(&self) -> &Vec<usize> {
+        &self.commits
+    }
+
     // Method to display tree information
     pub fn display_info(&self) {
         println!("Hash: {}", self.hash_index);
@@ -52,3 +72,5 @@ impl Properties for Tree {
         self.hash_index = index;
     }
 }
+
+impl_delta!(Tree);
@@ -1,8 +1,11 @@
 pub fn display_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = 1024 * KB;
+    const GB: u64 = 1024 * MB;
 
-    if bytes >= MB {
+    if bytes >= GB {
+        format!("{:.2} GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
         format!("{:.2} MB", bytes as f64 / MB as f64)
     } else if bytes >= KB {
         format!("{:.2} KB", bytes as f64 / KB as f64)
@@ -10,3 +13,70 @@ pub fn display_size(bytes: u64) -> String {
         format!("{bytes} bytes")
     }
 }
+
+/// Format seconds since the unix epoch as a UTC date and time, `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn display_time(seconds: i64) -> String {
+    let days = seconds.div_euclid(86_400);
+    let time = seconds.rem_euclid(86_400);
+
+    // Convert days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        time / 3600,
+        (time % 3600) / 60,
+        time % 60
+    )
+}
+
+/// Keep only the `count` largest of `items`, largest first. The largest are picked out before
+/// sorting, so only they are sorted rather than every object in the repo.
+pub fn keep_largest<T: Ord>(items: &mut Vec<T>, count: usize) {
+    if items.len() > count {
+        items.select_nth_unstable_by(count, |a, b| b.cmp(a));
+        items.truncate(count);
+    }
+    items.sort_unstable_by(|a, b| b.cmp(a));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_size() {
+        assert_eq!(display_size(512), "512 bytes");
+        assert_eq!(display_size(1536), "1.50 KB");
+        assert_eq!(display_size(5 * 1024 * 1024), "5.00 MB");
+        // larger than a u32 can hold
+        assert_eq!(display_size(6 * 1024 * 1024 * 1024), "6.00 GB");
+    }
+
+    #[test]
+    fn test_keep_largest() {
+        let mut items: Vec<u64> = (0..100).map(|i| (i * 37) % 100).collect();
+        keep_largest(&mut items, 3);
+        assert_eq!(items, vec![99, 98, 97]);
+
+        let mut few = vec![2, 5, 1];
+        keep_largest(&mut few, 10);
+        assert_eq!(few, vec![5, 2, 1]);
+    }
+
+    #[test]
+    fn test_display_time() {
+        assert_eq!(display_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(display_time(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(display_time(1_700_000_000), "2023-11-14 22:13:20 UTC");
+    }
+}